@@ -13,6 +13,9 @@ const PATH_PREFIX: &str = "cells/";
 const BUF_SIZE: usize = 8 * 1024;
 const CKB_HASH_PERSONALIZATION: &[u8] = b"ckb-default-hash";
 
+// Code hashes are part of every polyjuice cell, changing them orphans all
+// deployed cells. They are pinned here so a lock script rebuild never changes
+// them by accident, update them together with the C source deliberately.
 const BINARIES: &[(&str, &str)] = &[
     (
         "lock",
@@ -32,6 +35,7 @@ fn main() {
 
     for (name, expected_hash) in BINARIES {
         let path = format!("{}{}", PATH_PREFIX, name);
+        println!("cargo:rerun-if-changed={}", path);
 
         let mut buf = [0u8; BUF_SIZE];
         bundled
@@ -12,7 +12,8 @@
 #define ns(x) FLATBUFFERS_WRAP_NAMESPACE(Ckb_Protocol, x)
 
 int main(int argc, char* argv[]) {
-  if (argc != 2) {
+  /* program <ETH address> <chain ID> */
+  if (argc != 3) {
     return ERROR_ARGUMENTS;
   }
 
@@ -38,12 +38,18 @@ typedef unsigned __int128 uint128_t;
 #define ERROR_CHAIN_ID_NOT_FIT -25
 #define ERROR_V -26
 #define ERROR_OVERFLOW -27
+#define ERROR_INVALID_CHAIN_ID -28
+
+/* Access lists in typed transactions need quite some tokens */
+#define MAX_RLP_TOKENS 256
+
+#define LEGACY_TRANSACTION_TYPE 0x00
+#define ACCESS_LIST_TRANSACTION_TYPE 0x01
+#define DYNAMIC_FEE_TRANSACTION_TYPE 0x02
 
 #undef ns
 #define ns(x) FLATBUFFERS_WRAP_NAMESPACE(Ckb_Protocol, x)
 
-#define CHAIN_ID 1
-
 /*
  * We are including secp256k1 implementation directly so gcc can strip
  * unused functions. For some unknown reasons, if we link in libsecp256k1.a
@@ -373,7 +379,7 @@ int validate_from_to(char* argv[]) {
     return ERROR_LOAD_SCRIPT;
   }
   ns(Bytes_vec_t) args = ns(Script_args(script_table));
-  if (ns(Bytes_vec_len(args)) != 1) {
+  if (ns(Bytes_vec_len(args)) != 2) {
     return ERROR_INVALID_SCRIPT;
   }
   uint8_t buffer[20];
@@ -446,10 +452,19 @@ int validate_signature(const uint8_t* message, const uint8_t* compact_signature,
 }
 
 int main(int argc, char* argv[]) {
-  /* program <ETH address> <RLP serialization of ETH transaction> */
-  if (argc != 2) {
+  /*
+   * program <ETH address> <chain ID>, chain ID is a 64 bit little endian
+   * integer, so one build of this script serves every chain ID. RLP
+   * serialization of ETH transaction comes from witness.
+   */
+  if (argc != 3) {
     return ERROR_ARGUMENTS;
   }
+  if (ckb_argv_length(argv, 2) != 8) {
+    return ERROR_ARGUMENTS;
+  }
+  uint64_t chain_id = 0;
+  memcpy(&chain_id, argv[2], 8);
 
   uint8_t current_script_hash[32];
   volatile uint64_t len = 32;
@@ -514,21 +529,88 @@ int main(int argc, char* argv[]) {
     return ERROR_INVALID_NONCE;
   }
 
-  crlp_token_t tokens[16];
-  int token_size = crlp_parse_static(data, data_length, tokens, 16);
+  /*
+   * EIP-2718 typed transactions are prefixed with a single type byte,
+   * legacy transactions start with a RLP list header directly.
+   */
+  uint8_t transaction_type = LEGACY_TRANSACTION_TYPE;
+  size_t payload_offset = 0;
+  if (data[0] == ACCESS_LIST_TRANSACTION_TYPE ||
+      data[0] == DYNAMIC_FEE_TRANSACTION_TYPE) {
+    transaction_type = data[0];
+    payload_offset = 1;
+  }
+  uint8_t* payload = &data[payload_offset];
+  size_t payload_length = data_length - payload_offset;
+
+  /*
+   * Field positions of each transaction type:
+   * legacy: [nonce, gasPrice, gasLimit, to, value, data, v, r, s]
+   * 0x01: [chainId, nonce, gasPrice, gasLimit, to, value, data, accessList,
+   *        yParity, r, s]
+   * 0x02: [chainId, nonce, maxPriorityFeePerGas, maxFeePerGas, gasLimit, to,
+   *        value, data, accessList, yParity, r, s]
+   * For 0x02 transactions, maxFeePerGas is used as the gas price here since
+   * it is the upper bound of the fee paid.
+   */
+  int field_count, nonce_index, gas_price_index, gas_limit_index, value_index,
+      v_index;
+  switch (transaction_type) {
+    case ACCESS_LIST_TRANSACTION_TYPE:
+      field_count = 11;
+      nonce_index = 1;
+      gas_price_index = 2;
+      gas_limit_index = 3;
+      value_index = 5;
+      v_index = 8;
+      break;
+    case DYNAMIC_FEE_TRANSACTION_TYPE:
+      field_count = 12;
+      nonce_index = 1;
+      gas_price_index = 3;
+      gas_limit_index = 4;
+      value_index = 6;
+      v_index = 9;
+      break;
+    default:
+      field_count = 9;
+      nonce_index = 0;
+      gas_price_index = 1;
+      gas_limit_index = 2;
+      value_index = 4;
+      v_index = 6;
+      break;
+  }
+
+  crlp_token_t tokens[MAX_RLP_TOKENS];
+  int token_size =
+      crlp_parse_static(payload, payload_length, tokens, MAX_RLP_TOKENS);
   if (token_size < 0) {
     return token_size;
   }
   if ((!crlp_token_is_list(&tokens[0])) ||
-      (crlp_token_list_size(&tokens[0]) != 9)) {
+      (crlp_token_list_size(&tokens[0]) != field_count)) {
     return ERROR_RLP;
   }
 
-  /* Verify nonce in Ethereum matches nonce in CKB */
   int list_start_token = tokens[0].data.list.start_token;
-  crlp_token_t nonce_token = tokens[list_start_token + 0];
+  if (transaction_type != LEGACY_TRANSACTION_TYPE) {
+    uint128_t rlp_chain_id = 0;
+    ret = rlp_string_to_integer(payload, payload_length,
+                                &tokens[list_start_token + 0], &rlp_chain_id);
+    if (ret != CKB_SUCCESS) {
+      return ret;
+    }
+    if (rlp_chain_id != (uint128_t)chain_id) {
+      return ERROR_INVALID_CHAIN_ID;
+    }
+  }
+
+  /* Verify nonce in Ethereum matches nonce in CKB */
+  crlp_token_t nonce_token = tokens[list_start_token + nonce_index];
   uint128_t rlp_nonce = 0;
-  ret = rlp_string_to_integer(data, data_length, &nonce_token, &rlp_nonce);
+  ret = rlp_string_to_integer(payload, payload_length, &nonce_token,
+                              &rlp_nonce);
   if (ret != CKB_SUCCESS) {
     return ret;
   }
@@ -540,32 +622,57 @@ int main(int argc, char* argv[]) {
    * Verify value and fee in RLP match the value in transaction
    * to avoid malleability.
    */
-  uint128_t gas_price = 0, gas_limit = 0, value;
-  ret = rlp_string_to_integer(data, data_length, &tokens[list_start_token + 1],
+  uint128_t gas_price = 0, gas_limit = 0, value = 0;
+  ret = rlp_string_to_integer(payload, payload_length,
+                              &tokens[list_start_token + gas_price_index],
                               &gas_price);
   if (ret != CKB_SUCCESS) {
     return ret;
   }
-  ret = rlp_string_to_integer(data, data_length, &tokens[list_start_token + 2],
+  ret = rlp_string_to_integer(payload, payload_length,
+                              &tokens[list_start_token + gas_limit_index],
                               &gas_limit);
   if (ret != CKB_SUCCESS) {
     return ret;
   }
-  ret = rlp_string_to_integer(data, data_length, &tokens[list_start_token + 4],
-                              &value);
-  if (ret != CKB_SUCCESS) {
-    return ret;
+  crlp_token_t value_token = tokens[list_start_token + value_index];
+  if (!crlp_token_is_string(&value_token)) {
+    return ERROR_RLP;
+  }
+  /* A value of 0 is encoded as an empty string */
+  if (crlp_token_string_length(&value_token) > 0) {
+    ret = rlp_string_to_integer(payload, payload_length, &value_token, &value);
+    if (ret != CKB_SUCCESS) {
+      return ret;
+    }
   }
   uint128_t from_wei = ((uint128_t)from_capacity) * CAPACITY_TO_WEI;
-  ;
   uint128_t change_wei = ((uint128_t)change_capacity) * CAPACITY_TO_WEI;
   uint128_t gas_wei = gas_price * gas_limit;
-  if (from_wei != change_wei + gas_wei + value) {
-    return ERROR_INVALID_CAPACITY;
-  }
   uint128_t other_wei = ((uint128_t)other_capacity) * CAPACITY_TO_WEI;
   uint128_t sent_wei = ((uint128_t)sent_capacity) * CAPACITY_TO_WEI;
-  if (from_wei + other_wei != gas_wei + change_wei + sent_wei) {
+  /*
+   * Value moves between contracts and other accounts depending on EVM
+   * execution, and contracts could send value back to the sender, so the
+   * fee, which is whatever is not put in outputs, is verified in total.
+   * Unused gas is refunded to the change cell, so the fee actually paid
+   * can be anything between 0 and gas_price * gas_limit.
+   */
+  if (from_wei + other_wei < change_wei + sent_wei) {
+    return ERROR_INVALID_CAPACITY;
+  }
+  if (from_wei + other_wei > change_wei + sent_wei + gas_wei) {
+    return ERROR_INVALID_CAPACITY;
+  }
+  /*
+   * No matter how outputs are built, the sender never pays more than value
+   * and gas_price * gas_limit signed in the Ethereum transaction.
+   */
+  uint128_t max_spent_wei = value + gas_wei;
+  if (max_spent_wei < value) {
+    return ERROR_OVERFLOW;
+  }
+  if (from_wei > change_wei + max_spent_wei) {
     return ERROR_INVALID_CAPACITY;
   }
 
@@ -577,12 +684,20 @@ int main(int argc, char* argv[]) {
     return ret;
   }
 
-  crlp_token_t v = tokens[list_start_token + 6];
-  crlp_token_t r = tokens[list_start_token + 7];
-  crlp_token_t s = tokens[list_start_token + 8];
-  if ((!crlp_token_is_string(&v)) || (crlp_token_string_length(&v) != 1)) {
+  crlp_token_t v = tokens[list_start_token + v_index];
+  crlp_token_t r = tokens[list_start_token + v_index + 1];
+  crlp_token_t s = tokens[list_start_token + v_index + 2];
+  uint128_t v_value = 0;
+  if (!crlp_token_is_string(&v)) {
     return ERROR_RLP;
   }
+  /* A y parity of 0 is encoded as an empty string */
+  if (crlp_token_string_length(&v) > 0) {
+    ret = rlp_string_to_integer(payload, payload_length, &v, &v_value);
+    if (ret != CKB_SUCCESS) {
+      return ret;
+    }
+  }
   if ((!crlp_token_is_string(&r)) || (crlp_token_string_length(&r) != 32)) {
     return ERROR_RLP;
   }
@@ -590,22 +705,46 @@ int main(int argc, char* argv[]) {
     return ERROR_RLP;
   }
 
-  /* TODO: support longer chain ID later */
-  if (CHAIN_ID > 0xFF) {
-    return ERROR_CHAIN_ID_NOT_FIT;
+  uint128_t chain_v = ((uint128_t)chain_id) * 2 + 35;
+  if (transaction_type != LEGACY_TRANSACTION_TYPE) {
+    /* Typed transactions sign all fields but the signature ones */
+    tokens[0] =
+        crlp_create_list_token(list_start_token, list_start_token + v_index);
+  } else if (v_value >= chain_v) {
+    /* Chain ID is appended as a big endian integer without leading zeros */
+    if (data_length + 8 > 32768) {
+      return ERROR_CHAIN_ID_NOT_FIT;
+    }
+    size_t chain_id_start = payload_length;
+    for (int i = 7; i >= 0; i--) {
+      uint8_t b = (uint8_t)(chain_id >> (i * 8));
+      if (b != 0 || payload_length > chain_id_start) {
+        payload[payload_length++] = b;
+      }
+    }
+    tokens[list_start_token + 6] =
+        crlp_create_string_token(chain_id_start, payload_length);
+    tokens[list_start_token + 7] = crlp_create_string_token(0, 0);
+    tokens[list_start_token + 8] = crlp_create_string_token(0, 0);
+  } else {
+    /*
+     * Pre EIP-155 transactions only sign the first 6 fields. They are always
+     * accepted here, allow_unprotected_transactions of polyjuice is only
+     * enforced when transactions are submitted via its RPC.
+     */
+    tokens[0] = crlp_create_list_token(list_start_token, list_start_token + 6);
   }
-  data[data_length++] = CHAIN_ID;
-  tokens[list_start_token + 6] =
-      crlp_create_string_token(data_length - 1, data_length);
-  tokens[list_start_token + 7] = crlp_create_string_token(0, 0);
-  tokens[list_start_token + 8] = crlp_create_string_token(0, 0);
 
+  /* The signing payload of typed transactions keeps the type prefix */
   uint8_t unsigned_data[32768];
-  ssize_t unsigned_data_length = crlp_assemble(
-      data, data_length, tokens, token_size, 0, unsigned_data, 32768);
+  unsigned_data[0] = transaction_type;
+  ssize_t unsigned_data_length =
+      crlp_assemble(payload, payload_length, tokens, token_size, 0,
+                    &unsigned_data[payload_offset], 32768 - payload_offset);
   if (unsigned_data_length < 0) {
     return unsigned_data_length;
   }
+  unsigned_data_length += payload_offset;
 
   sha3_ctx_t ctx;
   sha3_init(&ctx, 32);
@@ -613,19 +752,23 @@ int main(int argc, char* argv[]) {
   uint8_t message[32];
   keccak_final(message, &ctx);
 
-  uint8_t bit = *crlp_token_string_pointer(data, data_length, &v);
-  if (bit >= CHAIN_ID * 2 + 35) {
-    bit -= CHAIN_ID * 2 + 35;
-  } else {
-    bit -= 27;
+  /* For typed transactions, y parity is used as recovery ID directly */
+  if (transaction_type == LEGACY_TRANSACTION_TYPE) {
+    if (v_value >= chain_v) {
+      v_value -= chain_v;
+    } else {
+      v_value -= 27;
+    }
   }
-  if (!(bit == 0 || bit == 1)) {
+  if (!(v_value == 0 || v_value == 1)) {
     return ERROR_V;
   }
+  uint8_t bit = (uint8_t)v_value;
 
   uint8_t input[64];
-  memcpy(input, crlp_token_string_pointer(data, data_length, &r), 32);
-  memcpy(&input[32], crlp_token_string_pointer(data, data_length, &s), 32);
+  memcpy(input, crlp_token_string_pointer(payload, payload_length, &r), 32);
+  memcpy(&input[32], crlp_token_string_pointer(payload, payload_length, &s),
+         32);
 
   ret = validate_signature(message, input, bit, argv);
   if (ret != CKB_SUCCESS) {
@@ -0,0 +1,345 @@
+use crate::{storage::DEFAULT_CHAIN_ID, Error};
+use clap::{value_t, App, Arg, ArgMatches};
+use serde_derive::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub ckb_uri: String,
+    // RocksDB path, different instances must use different paths
+    pub data_dir: PathBuf,
+    // Chain ID is part of lock script args of polyjuice cells
+    pub chain_id: u64,
+    pub allow_unprotected_transactions: bool,
+    pub rpc: RpcConfig,
+    pub indexer: IndexerConfig,
+    pub logger: LoggerConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ckb_uri: "http://127.0.0.1:8114".to_string(),
+            data_dir: PathBuf::from("./data"),
+            chain_id: DEFAULT_CHAIN_ID,
+            allow_unprotected_transactions: false,
+            rpc: RpcConfig::default(),
+            indexer: IndexerConfig::default(),
+            logger: LoggerConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    pub listen_address: SocketAddr,
+    pub ws_listen_address: SocketAddr,
+    // IPC endpoint is only started when a socket path is given
+    pub ipc_path: Option<PathBuf>,
+    pub ipc_permissions: u32,
+    pub threads: usize,
+    pub max_request_body_size: usize,
+    // "*" allows any origin, "null" allows null origin
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            listen_address: "127.0.0.1:8214".parse().expect("parse listen address"),
+            ws_listen_address: "127.0.0.1:8215".parse().expect("parse ws listen address"),
+            ipc_path: None,
+            ipc_permissions: 0o600,
+            threads: 4,
+            max_request_body_size: 10485760,
+            cors_origins: vec!["null".to_string(), "*".to_string()],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexerConfig {
+    // Milliseconds to wait before polling CKB again when no new block is
+    // available
+    pub poll_interval: u64,
+}
+
+impl IndexerConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval)
+    }
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            poll_interval: 3000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggerConfig {
+    pub format: LogFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::MalformedData(format!("Invalid log format: {}", s))),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::MalformedData(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&content).map_err(|e| {
+            Error::MalformedData(format!("Invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    // Loads config file given by --config, then applies command line
+    // overrides. Defaults are used when no config file is given.
+    pub fn from_args() -> Result<Self, Error> {
+        let matches = app().get_matches();
+        let mut config = match matches.value_of("config") {
+            Some(path) => Config::from_file(Path::new(path))?,
+            None => Config::default(),
+        };
+        config.apply_matches(&matches)?;
+        Ok(config)
+    }
+
+    fn apply_matches(&mut self, matches: &ArgMatches) -> Result<(), Error> {
+        if let Some(ckb_uri) = matches.value_of("ckb-uri") {
+            self.ckb_uri = ckb_uri.to_string();
+        }
+        if let Some(data_dir) = matches.value_of("data-dir") {
+            self.data_dir = PathBuf::from(data_dir);
+        }
+        if matches.is_present("chain-id") {
+            self.chain_id = value_t!(matches, "chain-id", u64).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("allow-unprotected-tx") {
+            self.allow_unprotected_transactions = true;
+        }
+        if matches.is_present("listen-address") {
+            self.rpc.listen_address =
+                value_t!(matches, "listen-address", SocketAddr).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("ws-listen-address") {
+            self.rpc.ws_listen_address =
+                value_t!(matches, "ws-listen-address", SocketAddr).unwrap_or_else(|e| e.exit());
+        }
+        if let Some(ipc_path) = matches.value_of("ipc-path") {
+            self.rpc.ipc_path = Some(PathBuf::from(ipc_path));
+        }
+        if let Some(permissions) = matches.value_of("ipc-permissions") {
+            self.rpc.ipc_permissions = u32::from_str_radix(permissions, 8).map_err(|_| {
+                Error::MalformedData(format!("Invalid IPC permissions: {}", permissions))
+            })?;
+        }
+        if matches.is_present("threads") {
+            self.rpc.threads = value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("max-request-body-size") {
+            self.rpc.max_request_body_size =
+                value_t!(matches, "max-request-body-size", usize).unwrap_or_else(|e| e.exit());
+        }
+        if let Some(origins) = matches.values_of("cors-origin") {
+            self.rpc.cors_origins = origins.map(|origin| origin.to_string()).collect();
+        }
+        if matches.is_present("poll-interval") {
+            self.indexer.poll_interval =
+                value_t!(matches, "poll-interval", u64).unwrap_or_else(|e| e.exit());
+        }
+        if let Some(format) = matches.value_of("log-format") {
+            self.logger.format = format.parse()?;
+        }
+        Ok(())
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let option = |name: &'static str, help: &'static str| {
+        Arg::with_name(name).long(name).takes_value(true).help(help)
+    };
+    App::new("polyjuice")
+        .about("Web3 compatible layer 2 on top of Nervos CKB")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("TOML config file, command line options take precedence"),
+        )
+        .arg(option("ckb-uri", "CKB RPC URI"))
+        .arg(option("data-dir", "RocksDB path"))
+        .arg(option("chain-id", "Chain ID used in lock script args"))
+        .arg(
+            Arg::with_name("allow-unprotected-tx")
+                .long("allow-unprotected-tx")
+                .help("Accept transactions signed without EIP-155 replay protection via RPC"),
+        )
+        .arg(option("listen-address", "HTTP JSON RPC listen address"))
+        .arg(option(
+            "ws-listen-address",
+            "WebSocket JSON RPC listen address",
+        ))
+        .arg(option(
+            "ipc-path",
+            "Unix domain socket path for IPC JSON RPC",
+        ))
+        .arg(option("ipc-permissions", "IPC socket permissions in octal"))
+        .arg(option("threads", "HTTP JSON RPC server threads"))
+        .arg(option(
+            "max-request-body-size",
+            "Max JSON RPC request body size in bytes",
+        ))
+        .arg(
+            option(
+                "cors-origin",
+                "Allowed CORS origin, could be repeated, \"*\" allows any origin",
+            )
+            .multiple(true)
+            .number_of_values(1),
+        )
+        .arg(option(
+            "poll-interval",
+            "Indexer poll interval in milliseconds",
+        ))
+        .arg(option("log-format", "Log format").possible_values(&["text", "json"]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_config_matches_defaults() {
+        let config: Config = toml::from_str(include_str!("../polyjuice.toml")).expect("parse");
+        let default = Config::default();
+        assert_eq!(config.ckb_uri, default.ckb_uri);
+        assert_eq!(config.data_dir, default.data_dir);
+        assert_eq!(config.chain_id, default.chain_id);
+        assert_eq!(
+            config.allow_unprotected_transactions,
+            default.allow_unprotected_transactions
+        );
+        assert_eq!(config.rpc.listen_address, default.rpc.listen_address);
+        assert_eq!(config.rpc.ws_listen_address, default.rpc.ws_listen_address);
+        assert_eq!(config.rpc.ipc_path, default.rpc.ipc_path);
+        assert_eq!(config.rpc.ipc_permissions, 0o600);
+        assert_eq!(config.rpc.threads, default.rpc.threads);
+        assert_eq!(
+            config.rpc.max_request_body_size,
+            default.rpc.max_request_body_size
+        );
+        assert_eq!(config.rpc.cors_origins, default.rpc.cors_origins);
+        assert_eq!(config.indexer.poll_interval, default.indexer.poll_interval);
+        assert_eq!(config.logger.format, LogFormat::Text);
+    }
+
+    #[test]
+    fn partial_config_uses_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            chain_id = 1024
+
+            [rpc]
+            ipc_path = "/tmp/polyjuice.ipc"
+
+            [logger]
+            format = "json"
+            "#,
+        )
+        .expect("parse");
+        assert_eq!(config.chain_id, 1024);
+        assert_eq!(config.ckb_uri, "http://127.0.0.1:8114");
+        assert_eq!(
+            config.rpc.ipc_path,
+            Some(PathBuf::from("/tmp/polyjuice.ipc"))
+        );
+        assert_eq!(config.rpc.threads, 4);
+        assert_eq!(config.indexer.poll_interval(), Duration::from_millis(3000));
+        assert_eq!(config.logger.format, LogFormat::Json);
+    }
+
+    #[test]
+    fn reject_invalid_config() {
+        assert!(toml::from_str::<Config>("chain_idd = 1").is_err());
+        assert!(toml::from_str::<Config>("[rpc]\nthread = 4").is_err());
+        assert!(toml::from_str::<Config>("[logger]\nformat = \"xml\"").is_err());
+        assert!(toml::from_str::<Config>("chain_id = \"1\"").is_err());
+    }
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("text".parse::<LogFormat>().expect("parse"), LogFormat::Text);
+        assert_eq!("json".parse::<LogFormat>().expect("parse"), LogFormat::Json);
+        assert!("JSON".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let mut config: Config = toml::from_str("chain_id = 1024").expect("parse");
+        let matches = app().get_matches_from(vec![
+            "polyjuice",
+            "--chain-id",
+            "3",
+            "--allow-unprotected-tx",
+            "--ipc-permissions",
+            "660",
+            "--cors-origin",
+            "http://localhost:3000",
+            "--cors-origin",
+            "null",
+            "--log-format",
+            "json",
+        ]);
+        config.apply_matches(&matches).expect("apply");
+        assert_eq!(config.chain_id, 3);
+        assert!(config.allow_unprotected_transactions);
+        assert_eq!(config.rpc.ipc_permissions, 0o660);
+        assert_eq!(
+            config.rpc.cors_origins,
+            vec!["http://localhost:3000".to_string(), "null".to_string()]
+        );
+        assert_eq!(config.logger.format, LogFormat::Json);
+        // Options not given keep values from config
+        assert_eq!(config.rpc.threads, 4);
+
+        let matches = app().get_matches_from(vec!["polyjuice", "--ipc-permissions", "rw"]);
+        assert!(config.apply_matches(&matches).is_err());
+    }
+}
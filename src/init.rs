@@ -1,50 +1,289 @@
-use bincode::serialize;
+use bincode::{deserialize, serialize};
 use bytes::{BufMut, Bytes, BytesMut};
 use ckb_core::{
     script::{Script as CoreScript, ScriptHashType as CoreScriptHashType},
     transaction::{
         CellInput as CoreCellInput, CellOutPoint as CoreCellOutPoint, CellOutput as CoreCellOutput,
-        OutPoint as CoreOutPoint, TransactionBuilder as CoreTransactionBuilder,
+        OutPoint as CoreOutPoint, Transaction as CoreTransaction,
+        TransactionBuilder as CoreTransactionBuilder,
     },
 };
 use ckb_hash::blake2b_256;
-use ckb_jsonrpc_types::{BlockNumber, CellOutPoint, Unsigned};
-use ckb_occupied_capacity::AsCapacity;
+use ckb_jsonrpc_types::{BlockNumber, CellOutPoint, OutPoint, Transaction, TxStatus, Unsigned};
+use ckb_occupied_capacity::Capacity;
 use ckb_sdk::HttpRpcClient;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use faster_hex::hex_decode;
 use numext_fixed_hash::H256;
 use polyjuice::{
+    config::Config,
     storage::{CONTRACT_LOCK_CODE_DEP_KEY, LOCK_CODE_DEP_KEY},
-    BUNDLED_CELL, SECP256K1,
+    Error, BUNDLED_CELL, CODE_HASH_CONTRACT_LOCK, CODE_HASH_LOCK, SECP256K1,
 };
 use rocksdb::DB;
 use secp256k1::{Message, PublicKey, SecretKey};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+// Environment variable holding the private key when no key file is given
+const PRIVATE_KEY_ENV: &str = "POLYJUICE_PRIVATE_KEY";
+// Time to wait between checks of the deployment transaction
+const COMMIT_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+struct LockCell {
+    name: &'static str,
+    bundle_path: &'static str,
+    code_hash: [u8; 32],
+    dep_key: &'static str,
+}
+
+const LOCK_CELLS: [LockCell; 2] = [
+    LockCell {
+        name: "lock",
+        bundle_path: "cells/lock",
+        code_hash: CODE_HASH_LOCK,
+        dep_key: LOCK_CODE_DEP_KEY,
+    },
+    LockCell {
+        name: "contract-lock",
+        bundle_path: "cells/contract_lock",
+        code_hash: CODE_HASH_CONTRACT_LOCK,
+        dep_key: CONTRACT_LOCK_CODE_DEP_KEY,
+    },
+];
+
+// Out points of lock cells, indexed the same as LOCK_CELLS, together with
+// the transaction deploying missing ones if any.
+struct Deployment {
+    out_points: Vec<CellOutPoint>,
+    transaction: Option<CoreTransaction>,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.len() != 1 || args[0].len() != 64 {
+    let matches = app().get_matches();
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
         exit(1);
     }
+}
+
+fn app() -> App<'static, 'static> {
+    let private_key_path = Arg::with_name("private-key-path")
+        .long("private-key-path")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Hex encoded private key file, POLYJUICE_PRIVATE_KEY is used if not given");
+    let out_point = |name: &'static str, help: &'static str| {
+        Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("TX_HASH:INDEX")
+            .required(true)
+            .help(help)
+    };
+    App::new("init")
+        .about("Deploys lock scripts used by polyjuice cells to CKB")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("polyjuice TOML config file, command line options take precedence"),
+        )
+        .arg(
+            Arg::with_name("ckb-uri")
+                .long("ckb-uri")
+                .takes_value(true)
+                .help("CKB RPC URI"),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .takes_value(true)
+                .help("RocksDB path"),
+        )
+        .subcommand(
+            SubCommand::with_name("deploy")
+                .about("Deploys lock cells missing on chain and registers all of them")
+                .arg(private_key_path.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("dry-run")
+                .about("Prints the deployment transaction without sending it")
+                .arg(private_key_path),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Verifies registered lock cells are live on chain"),
+        )
+        .subcommand(
+            SubCommand::with_name("register")
+                .about("Registers lock cells that are already deployed without sending anything")
+                .arg(out_point("lock", "Out point of lock cell"))
+                .arg(out_point(
+                    "contract-lock",
+                    "Out point of contract lock cell",
+                )),
+        )
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => Config::from_file(Path::new(path))?,
+        None => Config::default(),
+    };
+    if let Some(ckb_uri) = matches.value_of("ckb-uri") {
+        config.ckb_uri = ckb_uri.to_string();
+    }
+    if let Some(data_dir) = matches.value_of("data-dir") {
+        config.data_dir = PathBuf::from(data_dir);
+    }
+    let mut client = HttpRpcClient::from_uri(&config.ckb_uri);
+    let open_db = || DB::open_default(&config.data_dir);
+
+    match matches.subcommand() {
+        ("deploy", Some(matches)) => {
+            let secret_key = load_secret_key(matches)?;
+            let db = open_db()?;
+            let deployment = build_deployment(&mut client, &db, &secret_key)?;
+            match &deployment.transaction {
+                Some(transaction) => {
+                    let tx_hash = client.send_transaction(transaction.into()).call()?;
+                    println!("TX hash: {:x}", tx_hash);
+                    // Lock cells are only registered once they are live, so
+                    // polyjuice never starts with deps that are still pending.
+                    println!("Waiting for the transaction to be committed...");
+                    wait_for_commit(&mut client, &tx_hash)?;
+                }
+                None => println!("All lock cells are already deployed."),
+            }
+            register(&db, &deployment.out_points)?;
+            println!("All done!");
+        }
+        ("dry-run", Some(matches)) => {
+            let secret_key = load_secret_key(matches)?;
+            let db = open_db()?;
+            let deployment = build_deployment(&mut client, &db, &secret_key)?;
+            for (cell, out_point) in LOCK_CELLS.iter().zip(&deployment.out_points) {
+                println!("{}: {}", cell.name, format_out_point(out_point));
+            }
+            match &deployment.transaction {
+                Some(transaction) => {
+                    let transaction: Transaction = transaction.into();
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&transaction)
+                            .map_err(|e| Error::Data(e.to_string()))?
+                    );
+                }
+                None => println!("All lock cells are already deployed."),
+            }
+        }
+        ("verify", _) => {
+            let db = open_db()?;
+            let mut valid = true;
+            for cell in &LOCK_CELLS {
+                let out_point: CellOutPoint = match db.get(cell.dep_key)? {
+                    Some(data) => deserialize(&data)?,
+                    None => {
+                        println!("{}: not registered", cell.name);
+                        valid = false;
+                        continue;
+                    }
+                };
+                if is_lock_cell(&mut client, &out_point, cell)? {
+                    println!("{}: {} OK", cell.name, format_out_point(&out_point));
+                } else {
+                    println!(
+                        "{}: {} is not live or data hash does not match",
+                        cell.name,
+                        format_out_point(&out_point)
+                    );
+                    valid = false;
+                }
+            }
+            if !valid {
+                return Err(Error::MalformedData(
+                    "Lock cells are not deployed!".to_string(),
+                ));
+            }
+        }
+        ("register", Some(matches)) => {
+            let mut out_points = vec![];
+            for cell in &LOCK_CELLS {
+                let out_point = parse_out_point(matches.value_of(cell.name).expect("required"))?;
+                if !is_lock_cell(&mut client, &out_point, cell)? {
+                    return Err(Error::MalformedData(format!(
+                        "{} is not a live {} cell!",
+                        format_out_point(&out_point),
+                        cell.name
+                    )));
+                }
+                out_points.push(out_point);
+            }
+            register(&open_db()?, &out_points)?;
+            println!("All done!");
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn load_secret_key(matches: &ArgMatches) -> Result<SecretKey, Error> {
+    let content = match matches.value_of("private-key-path") {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| Error::MalformedData(format!("Failed to read {}: {}", path, e)))?,
+        None => env::var(PRIVATE_KEY_ENV).map_err(|_| {
+            Error::MalformedData(format!(
+                "Private key is not given, use --private-key-path or {}!",
+                PRIVATE_KEY_ENV
+            ))
+        })?,
+    };
+    parse_secret_key(&content)
+}
+
+// Private keys are 32 bytes in hex, optionally prefixed with 0x
+fn parse_secret_key(content: &str) -> Result<SecretKey, Error> {
+    let content = content.trim();
+    let content = content.trim_start_matches("0x");
+    if content.len() != 64 {
+        return Err(Error::MalformedData(
+            "Private key must be 32 bytes in hex!".to_string(),
+        ));
+    }
     let mut secret_key_bytes = [0u8; 32];
-    hex_decode(args[0].as_bytes(), &mut secret_key_bytes[..]).expect("hex decode");
-    let secret_key = SecretKey::from_slice(&secret_key_bytes[..]).expect("secret key");
-    let public_key = PublicKey::from_secret_key(&SECP256K1, &secret_key);
+    hex_decode(content.as_bytes(), &mut secret_key_bytes[..])
+        .map_err(|e| Error::MalformedData(format!("Invalid private key: {}", e)))?;
+    Ok(SecretKey::from_slice(&secret_key_bytes[..])?)
+}
+
+// Lock cells registered before or found in cells of the deployer are reused,
+// only missing ones are deployed, so running deploy again spends nothing.
+fn build_deployment(
+    client: &mut HttpRpcClient,
+    db: &DB,
+    secret_key: &SecretKey,
+) -> Result<Deployment, Error> {
+    let public_key = PublicKey::from_secret_key(&SECP256K1, secret_key);
     let public_key_bytes = public_key.serialize();
     let public_key_hash = blake2b_256(&public_key_bytes[..]);
     let blake160_hash = Bytes::from(&public_key_hash[..20]);
 
-    let ckb_uri = "http://127.0.0.1:8114";
-    let mut client = HttpRpcClient::from_uri(ckb_uri);
-
-    let genesis_block = client
-        .get_block_by_number(BlockNumber(0))
-        .call()
-        .expect("fetching genesis block")
-        .0
-        .unwrap();
+    let genesis_block =
+        client
+            .get_block_by_number(BlockNumber(0))
+            .call()?
+            .0
+            .ok_or(Error::MalformedData(
+                "Genesis block is missing!".to_string(),
+            ))?;
     let system_cell_transaction = genesis_block.transactions[0].clone();
     let secp_out_point = CoreOutPoint {
         cell: Some(CoreCellOutPoint {
@@ -62,69 +301,124 @@ fn main() {
     };
     let lock_hash = lock.hash();
 
-    let mut lock_cell = CoreCellOutput {
-        capacity: 0u64.as_capacity(),
-        data: Bytes::from(BUNDLED_CELL.get("cells/lock").unwrap().as_ref()),
-        lock: lock.clone(),
-        type_: None,
-    };
-    lock_cell.capacity = lock_cell.occupied_capacity().expect("occupied capacity");
-    let mut contract_lock_cell = CoreCellOutput {
-        capacity: 0u64.as_capacity(),
-        data: Bytes::from(BUNDLED_CELL.get("cells/contract_lock").unwrap().as_ref()),
-        lock: lock.clone(),
-        type_: None,
-    };
-    contract_lock_cell.capacity = contract_lock_cell
-        .occupied_capacity()
-        .expect("occupied capacity");
-    let total_capacity = lock_cell
-        .capacity
-        .safe_add(contract_lock_cell.capacity)
-        .expect("add capacity");
-
-    let mut current_capacity = 0u64.as_capacity();
-    let tip_number = client.get_tip_block_number().call().unwrap().0;
+    let mut out_points: Vec<Option<CellOutPoint>> = vec![None; LOCK_CELLS.len()];
+    for (cell, out_point) in LOCK_CELLS.iter().zip(out_points.iter_mut()) {
+        if let Some(data) = db.get(cell.dep_key)? {
+            let registered = deserialize(&data)?;
+            if is_lock_cell(client, &registered, cell)? {
+                *out_point = Some(registered);
+            }
+        }
+    }
+
+    // Lock cells owned by the deployer are found by data hash, cells without
+    // data are used to pay for the deployment.
+    let mut available_cells = vec![];
+    let tip_number = client.get_tip_block_number().call()?.0;
     let mut start = 0;
-    let mut inputs = vec![];
-    while start < tip_number {
+    while start <= tip_number {
         let cells = client
             .get_cells_by_lock_hash(
                 lock_hash.clone(),
                 BlockNumber(start),
                 BlockNumber(start + 100 - 1),
             )
-            .call()
-            .unwrap()
+            .call()?
             .0;
-        for cell in &cells {
-            current_capacity = current_capacity.safe_add(cell.capacity.0).unwrap();
-            inputs.push(CoreCellInput {
-                previous_output: cell.out_point.clone().into(),
-                since: 0,
-            });
+        for cell in cells {
+            let cell_out_point = match &cell.out_point.cell {
+                Some(cell_out_point) => cell_out_point.clone(),
+                None => continue,
+            };
+            let live_cell = match load_live_cell_data(client, &cell_out_point)? {
+                Some(data) => data,
+                None => continue,
+            };
+            if live_cell.is_empty() {
+                available_cells.push(cell);
+                continue;
+            }
+            let data_hash = blake2b_256(&live_cell);
+            for (lock_cell, out_point) in LOCK_CELLS.iter().zip(out_points.iter_mut()) {
+                if out_point.is_none() && data_hash == lock_cell.code_hash {
+                    *out_point = Some(cell_out_point.clone());
+                }
+            }
         }
+        start += 100;
+    }
+
+    let mut outputs = vec![];
+    for (cell, out_point) in LOCK_CELLS.iter().zip(&out_points) {
+        if out_point.is_none() {
+            let mut output = CoreCellOutput {
+                capacity: Capacity::zero(),
+                data: Bytes::from(
+                    BUNDLED_CELL
+                        .get(cell.bundle_path)
+                        .map_err(|e| Error::Data(e.to_string()))?
+                        .as_ref(),
+                ),
+                lock: lock.clone(),
+                type_: None,
+            };
+            output.capacity = output
+                .occupied_capacity()
+                .map_err(|e| Error::Data(format!("{:?}", e)))?;
+            outputs.push(output);
+        }
+    }
+    if outputs.is_empty() {
+        return Ok(Deployment {
+            out_points: out_points.into_iter().map(|o| o.expect("found")).collect(),
+            transaction: None,
+        });
+    }
+    let total_capacity = outputs
+        .iter()
+        .try_fold(Capacity::zero(), |sum, output| {
+            sum.safe_add(output.capacity)
+        })
+        .map_err(|e| Error::Data(format!("{:?}", e)))?;
+
+    let mut current_capacity = Capacity::zero();
+    let mut inputs = vec![];
+    for cell in available_cells {
         if current_capacity >= total_capacity {
             break;
         }
-        start += 100;
+        current_capacity = current_capacity
+            .safe_add(cell.capacity.0)
+            .map_err(|e| Error::Data(format!("{:?}", e)))?;
+        inputs.push(CoreCellInput {
+            previous_output: cell.out_point.clone().into(),
+            since: 0,
+        });
+    }
+    if current_capacity < total_capacity {
+        return Err(Error::MalformedData(format!(
+            "Insufficient capacity, {} is required but only {} is available!",
+            total_capacity.as_u64(),
+            current_capacity.as_u64()
+        )));
     }
     let mut transaction_builder = CoreTransactionBuilder::default()
         .dep(secp_out_point)
         .inputs(inputs)
-        .output(lock_cell)
-        .output(contract_lock_cell);
+        .outputs(outputs);
     if current_capacity > total_capacity {
         transaction_builder = transaction_builder.output(CoreCellOutput {
-            capacity: current_capacity.safe_sub(total_capacity).unwrap(),
+            capacity: current_capacity
+                .safe_sub(total_capacity)
+                .map_err(|e| Error::Data(format!("{:?}", e)))?,
             data: Bytes::default(),
             lock: lock.clone(),
             type_: None,
         });
     }
     let unsigned_transaction = transaction_builder.build();
-    let message = Message::from_slice(&blake2b_256(unsigned_transaction.hash())[..]).unwrap();
-    let signature = SECP256K1.sign_recoverable(&message, &secret_key);
+    let message = Message::from_slice(&blake2b_256(unsigned_transaction.hash())[..])?;
+    let signature = SECP256K1.sign_recoverable(&message, secret_key);
     let (recid, compact) = signature.serialize_compact();
     let mut witness_bytes = BytesMut::new();
     witness_bytes.extend_from_slice(&compact[..]);
@@ -136,32 +430,141 @@ fn main() {
         signed_builder = signed_builder.witness(vec![witness.clone()]);
     }
     let transaction = signed_builder.build();
-    let tx_hash = client
-        .send_transaction((&transaction).into())
-        .call()
-        .unwrap();
-    println!("TX hash: {:x}", tx_hash);
-
-    // Write to DB
-    let db = Arc::new(DB::open_default("./data").expect("rocksdb"));
-    db.put(
-        &LOCK_CODE_DEP_KEY,
-        &serialize(&CellOutPoint {
-            tx_hash: transaction.hash().clone(),
-            index: Unsigned(0),
+
+    // New lock cells are placed in outputs following the order of LOCK_CELLS
+    let mut output_index = 0;
+    let out_points = out_points
+        .into_iter()
+        .map(|out_point| {
+            out_point.unwrap_or_else(|| {
+                let out_point = CellOutPoint {
+                    tx_hash: transaction.hash().clone(),
+                    index: Unsigned(output_index),
+                };
+                output_index += 1;
+                out_point
+            })
         })
-        .expect("serialize"),
-    )
-    .expect("rocksdb write");
-    db.put(
-        &CONTRACT_LOCK_CODE_DEP_KEY,
-        &serialize(&CellOutPoint {
-            tx_hash: transaction.hash().clone(),
-            index: Unsigned(1),
+        .collect();
+    Ok(Deployment {
+        out_points,
+        transaction: Some(transaction),
+    })
+}
+
+fn load_live_cell_data(
+    client: &mut HttpRpcClient,
+    out_point: &CellOutPoint,
+) -> Result<Option<Bytes>, Error> {
+    let cell = client
+        .get_live_cell(OutPoint {
+            cell: Some(out_point.clone()),
+            block_hash: None,
         })
-        .expect("serialize"),
-    )
-    .expect("rocksdb write");
+        .call()?;
+    Ok(cell.cell.map(|cell| Bytes::from(cell.data.as_bytes())))
+}
+
+fn is_lock_cell(
+    client: &mut HttpRpcClient,
+    out_point: &CellOutPoint,
+    cell: &LockCell,
+) -> Result<bool, Error> {
+    Ok(load_live_cell_data(client, out_point)?
+        .map(|data| blake2b_256(&data) == cell.code_hash)
+        .unwrap_or(false))
+}
 
-    println!("All done!");
+// Polls CKB till the transaction is committed, fails if CKB drops it
+fn wait_for_commit(client: &mut HttpRpcClient, tx_hash: &H256) -> Result<(), Error> {
+    loop {
+        match client.get_transaction(tx_hash.clone()).call()?.0 {
+            Some(transaction) => {
+                // Status is not exposed in this CKB version, compare against a
+                // dummy committed status instead.
+                let dummy_tx_status = TxStatus::committed(tx_hash.clone());
+                if transaction.tx_status.status == dummy_tx_status.status {
+                    return Ok(());
+                }
+            }
+            None => {
+                return Err(Error::MalformedData(format!(
+                    "Transaction {:x} is dropped by CKB!",
+                    tx_hash
+                )))
+            }
+        }
+        sleep(COMMIT_POLL_INTERVAL);
+    }
+}
+
+fn register(db: &DB, out_points: &[CellOutPoint]) -> Result<(), Error> {
+    for (cell, out_point) in LOCK_CELLS.iter().zip(out_points) {
+        db.put(cell.dep_key, &serialize(out_point)?)?;
+        println!("Registered {}: {}", cell.name, format_out_point(out_point));
+    }
+    Ok(())
+}
+
+// Out points are written as <tx hash>:<index>
+fn parse_out_point(s: &str) -> Result<CellOutPoint, Error> {
+    let invalid = || Error::MalformedData(format!("Invalid out point: {}", s));
+    let mut parts = s.trim_start_matches("0x").splitn(2, ':');
+    let tx_hash = parts.next().ok_or_else(invalid)?;
+    let index = parts.next().ok_or_else(invalid)?;
+    if tx_hash.len() != 64 {
+        return Err(invalid());
+    }
+    let mut tx_hash_bytes = [0u8; 32];
+    hex_decode(tx_hash.as_bytes(), &mut tx_hash_bytes[..]).map_err(|_| invalid())?;
+    Ok(CellOutPoint {
+        tx_hash: H256::from_slice(&tx_hash_bytes[..]).map_err(|_| invalid())?,
+        index: Unsigned(index.parse().map_err(|_| invalid())?),
+    })
+}
+
+fn format_out_point(out_point: &CellOutPoint) -> String {
+    format!("0x{:x}:{}", out_point.tx_hash, out_point.index.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TX_HASH: &str = "b73b96c41fabd3769f920b4aa81a6b68cbc1b5d4499090bb4aeb77c2095b2cd4";
+    const PRIVATE_KEY: &str = "d00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc";
+
+    #[test]
+    fn parse_out_points() {
+        let out_point = parse_out_point(&format!("0x{}:1", TX_HASH)).expect("parse");
+        assert_eq!(format!("{:x}", out_point.tx_hash), TX_HASH);
+        assert_eq!(out_point.index.0, 1);
+        assert_eq!(format_out_point(&out_point), format!("0x{}:1", TX_HASH));
+        // 0x prefix is optional
+        let out_point = parse_out_point(&format!("{}:0", TX_HASH)).expect("parse");
+        assert_eq!(out_point.index.0, 0);
+    }
+
+    #[test]
+    fn reject_invalid_out_points() {
+        assert!(parse_out_point(TX_HASH).is_err());
+        assert!(parse_out_point(&format!("0x{}:", TX_HASH)).is_err());
+        assert!(parse_out_point(&format!("0x{}:-1", TX_HASH)).is_err());
+        assert!(parse_out_point(&format!("0x{}:1", &TX_HASH[2..])).is_err());
+        assert!(parse_out_point("").is_err());
+    }
+
+    #[test]
+    fn parse_secret_keys() {
+        let secret_key = parse_secret_key(PRIVATE_KEY).expect("parse");
+        // Key files usually end with a new line
+        assert_eq!(
+            parse_secret_key(&format!("0x{}\n", PRIVATE_KEY)).expect("parse"),
+            secret_key
+        );
+        assert!(parse_secret_key(&PRIVATE_KEY[2..]).is_err());
+        assert!(parse_secret_key(&format!("{}00", PRIVATE_KEY)).is_err());
+        // Zero is not a valid secp256k1 secret key
+        assert!(parse_secret_key(&"0".repeat(64)).is_err());
+    }
 }
@@ -3,6 +3,7 @@
 include!(concat!(env!("OUT_DIR"), "/bundled.rs"));
 include!(concat!(env!("OUT_DIR"), "/code_hashes.rs"));
 
+pub mod config;
 pub mod modules;
 pub mod storage;
 
@@ -10,8 +11,10 @@ pub mod storage;
 extern crate log;
 
 use bincode::Error as BincodeError;
+use bytes::Bytes;
+use faster_hex::hex_string;
 use jsonrpc_client_core::Error as ClientRpcError;
-use jsonrpc_core::{Error as ServerRpcError, ErrorCode as ServerRpcErrorCode};
+use jsonrpc_core::{Error as ServerRpcError, ErrorCode as ServerRpcErrorCode, Value};
 use lazy_static::lazy_static;
 use rlp::DecoderError;
 use rocksdb::Error as DBError;
@@ -33,11 +36,38 @@ pub enum Error {
     MalformedData(String),
     InvalidOutPoint,
     EVM(String),
+    // Execution reverted, holds raw revert data
+    Reverted(Bytes),
+}
+
+impl Error {
+    // JSON-RPC error code for each variant, these are part of the RPC
+    // interface so existing values should never be changed.
+    pub fn code(&self) -> i64 {
+        match self {
+            // Ethereum clients use 3 for execution reverted
+            Error::Reverted(_) => 3,
+            Error::DB(_) => -32001,
+            Error::Rpc(_) => -32002,
+            Error::Data(_) => -32003,
+            Error::Rlp(_) => -32004,
+            Error::Secp(_) => -32005,
+            Error::MalformedData(_) => -32006,
+            Error::InvalidOutPoint => -32007,
+            Error::EVM(_) => -32008,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::Reverted(data) => match storage::decode_revert_reason(data) {
+                Some(reason) => write!(f, "execution reverted: {}", reason),
+                None => write!(f, "execution reverted"),
+            },
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -79,10 +109,17 @@ impl From<EvmError> for Error {
 
 impl From<Error> for ServerRpcError {
     fn from(e: Error) -> ServerRpcError {
+        let data = match &e {
+            Error::Reverted(data) => Some(Value::String(format!(
+                "0x{}",
+                hex_string(data).expect("hex encode")
+            ))),
+            _ => None,
+        };
         ServerRpcError {
-            code: ServerRpcErrorCode::InvalidRequest,
+            code: ServerRpcErrorCode::ServerError(e.code()),
             message: e.to_string(),
-            data: None,
+            data,
         }
     }
 }
@@ -1,51 +1,135 @@
 #[macro_use]
 extern crate log;
 
-use jsonrpc_core::IoHandler;
+use jsonrpc_core::MetaIoHandler;
 use jsonrpc_http_server::ServerBuilder;
+use jsonrpc_ipc_server::{RequestContext as IpcRequestContext, ServerBuilder as IpcServerBuilder};
+use jsonrpc_pubsub::{PubSubHandler, Session};
 use jsonrpc_server_utils::cors::AccessControlAllowOrigin;
 use jsonrpc_server_utils::hosts::DomainsValidation;
+use jsonrpc_ws_server::{RequestContext, ServerBuilder as WsServerBuilder};
 use polyjuice::{
-    modules::{EthRpc, EthRpcImpl, Web3Rpc, Web3RpcImpl},
-    storage::{Indexer, Loader},
+    config::{Config, LogFormat},
+    modules::{
+        EthPubSub, EthPubSubImpl, EthRpc, EthRpcImpl, Filters, NetRpc, NetRpcImpl, TxPoolRpc,
+        TxPoolRpcImpl, Web3Rpc, Web3RpcImpl,
+    },
+    storage::{check_schema_version, Indexer, Loader, TxPool},
 };
 use rocksdb::DB;
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
 
 fn main() {
-    env_logger::init();
+    let config = Config::from_args().expect("config");
+    init_logger(config.logger.format);
 
     info!("starting...");
 
-    let db = Arc::new(DB::open_default("./data").expect("rocksdb"));
-    let ckb_uri = "http://127.0.0.1:8114";
-    let loader = Arc::new(Loader::new(Arc::clone(&db), ckb_uri).expect("loader failure"));
+    let db = Arc::new(DB::open_default(&config.data_dir).expect("rocksdb"));
+    check_schema_version(&db).expect("schema version");
+    let ckb_uri = config.ckb_uri.as_str();
+    let chain_id = config.chain_id;
+    let loader = Arc::new(Loader::new(Arc::clone(&db), ckb_uri, chain_id).expect("loader failure"));
 
-    let mut indexer = Indexer::from(Arc::clone(&db), ckb_uri);
+    let mut indexer = Indexer::from(Arc::clone(&db), ckb_uri, chain_id);
+    indexer.poll_interval = config.indexer.poll_interval();
+    let indexer_events = indexer.subscribe();
     let _ = thread::spawn(move || indexer.index().expect("indexer faliure"));
 
-    // RPC
-    let mut io_handler = IoHandler::new();
+    let txpool = Arc::new(TxPool::new(Arc::clone(&loader)));
+    let maintained_txpool = Arc::clone(&txpool);
+    let _ = thread::spawn(move || loop {
+        if let Err(e) = maintained_txpool.maintain() {
+            warn!("transaction pool maintenance failure: {:?}", e);
+        }
+        thread::sleep(Duration::from_secs(3));
+    });
+
+    let filters = Arc::new(Filters::new(Arc::clone(&loader), Arc::clone(&txpool)));
+
+    let pubsub = EthPubSubImpl::new(Arc::clone(&loader));
+    let indexer_pubsub = pubsub.clone();
+    let _ = thread::spawn(move || indexer_pubsub.notify_indexer_events(indexer_events));
+    let txpool_pubsub = pubsub.clone();
+    let txpool_events = txpool.subscribe().expect("txpool subscription");
+    let _ = thread::spawn(move || txpool_pubsub.notify_pending_transactions(txpool_events));
+
+    // RPC, subscriptions are only available via WebSocket
+    let mut io_handler: PubSubHandler<Option<Arc<Session>>> =
+        PubSubHandler::new(MetaIoHandler::default());
+    io_handler.extend_with(pubsub.to_delegate());
     io_handler.extend_with(Web3RpcImpl {}.to_delegate());
     io_handler.extend_with(
         EthRpcImpl {
             loader: Arc::clone(&loader),
+            txpool: Arc::clone(&txpool),
+            filters: Arc::clone(&filters),
+            allow_unprotected_transactions: config.allow_unprotected_transactions,
+        }
+        .to_delegate(),
+    );
+    io_handler.extend_with(
+        NetRpcImpl {
+            loader: Arc::clone(&loader),
+        }
+        .to_delegate(),
+    );
+    io_handler.extend_with(
+        TxPoolRpcImpl {
+            txpool: Arc::clone(&txpool),
         }
         .to_delegate(),
     );
 
-    let rpc_server = ServerBuilder::new(io_handler)
-        .cors(DomainsValidation::AllowOnly(vec![
-            AccessControlAllowOrigin::Null,
-            AccessControlAllowOrigin::Any,
-        ]))
-        // TODO parameterize following if needed
-        .threads(4)
-        .max_request_body_size(10485760)
-        .start_http(&"127.0.0.1:8214".parse().expect("parse listen address"))
+    let cors_origins: Vec<AccessControlAllowOrigin> = config
+        .rpc
+        .cors_origins
+        .iter()
+        .map(|origin| origin.as_str().into())
+        .collect();
+    let rpc_server = ServerBuilder::new(io_handler.clone())
+        .cors(DomainsValidation::AllowOnly(cors_origins))
+        .threads(config.rpc.threads)
+        .max_request_body_size(config.rpc.max_request_body_size)
+        .start_http(&config.rpc.listen_address)
         .expect("jsonrpc initialize");
 
+    let ipc_permissions = config.rpc.ipc_permissions;
+    let ipc_server = config.rpc.ipc_path.as_ref().map(|ipc_path| {
+        // Remove socket left by previous runs
+        if let Ok(metadata) = fs::metadata(&ipc_path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(&ipc_path).expect("remove stale IPC socket");
+            }
+        }
+        // Socket is created with owner only permissions, so it's never
+        // accessible by others before the configured permissions are set.
+        let previous_umask = unsafe { libc::umask(0o077) };
+        let ipc_server = IpcServerBuilder::with_meta_extractor(
+            io_handler.clone(),
+            |context: &IpcRequestContext| Some(Arc::new(Session::new(context.sender.clone()))),
+        )
+        .start(&ipc_path.to_string_lossy());
+        unsafe { libc::umask(previous_umask) };
+        let ipc_server = ipc_server.expect("ipc initialize");
+        fs::set_permissions(&ipc_path, fs::Permissions::from_mode(ipc_permissions))
+            .expect("set IPC socket permissions");
+        info!("IPC endpoint listening at {}", ipc_path.display());
+        ipc_server
+    });
+
+    let ws_server = WsServerBuilder::with_meta_extractor(io_handler, |context: &RequestContext| {
+        Some(Arc::new(Session::new(context.sender())))
+    })
+    .start(&config.rpc.ws_listen_address)
+    .expect("websocket initialize");
+
     // Wait for exit
     let exit = Arc::new((Mutex::new(()), Condvar::new()));
     let e = Arc::clone(&exit);
@@ -58,5 +142,26 @@ fn main() {
         .wait(exit.0.lock().expect("locking"))
         .expect("waiting");
     rpc_server.close();
+    ws_server.close();
+    if let Some(ipc_server) = ipc_server {
+        ipc_server.close();
+    }
     info!("exiting...");
 }
+
+// Log level is still controlled by RUST_LOG
+fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let entry = json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", entry)
+        });
+    }
+    builder.init();
+}
@@ -1,10 +1,13 @@
 use crate::{
-    modules::TransactionCall,
+    modules::{FilterChanges, Filters, LogFilter, TransactionCall},
     storage::{
-        serialize_u64, BlockNumber, EthAddress, EthTransaction, Loader, Runner, TransactionReceipt,
+        parse_u64, serialize_u64, Block, BlockNumber, BlockParameter, EthAccount, EthAddress,
+        EthTransaction, FeeHistory, Loader, Log, PoolTransactionObject, Runner, TransactionObject,
+        TransactionReceipt, TxPool,
     },
     Error as CrateError,
 };
+use bytes::Bytes;
 use ckb_jsonrpc_types::JsonBytes;
 use jsonrpc_core::{Error, Result};
 use jsonrpc_derive::rpc;
@@ -15,15 +18,53 @@ use std::sync::Arc;
 
 #[rpc]
 pub trait EthRpc {
+    #[rpc(name = "eth_chainId")]
+    fn chain_id(&self) -> Result<String>;
+
     #[rpc(name = "eth_blockNumber")]
     fn block_number(&self) -> Result<String>;
 
+    #[rpc(name = "eth_getBlockByNumber")]
+    fn get_block_by_number(
+        &self,
+        block_number: String,
+        full_transactions: bool,
+    ) -> Result<Option<Block>>;
+
+    #[rpc(name = "eth_getBlockByHash")]
+    fn get_block_by_hash(&self, block_hash: H256, full_transactions: bool)
+        -> Result<Option<Block>>;
+
     #[rpc(name = "eth_getBalance")]
-    fn get_balance(&self, eth_address: String, block_number: Option<String>) -> Result<U256>;
+    fn get_balance(
+        &self,
+        eth_address: String,
+        block_number: Option<BlockParameter>,
+    ) -> Result<U256>;
 
     #[rpc(name = "eth_sendRawTransaction")]
     fn send_raw_transaction(&self, raw: JsonBytes) -> Result<H256>;
 
+    #[rpc(name = "eth_getTransactionByHash")]
+    fn get_transaction_by_hash(&self, hash: H256) -> Result<Option<TransactionObject>>;
+
+    #[rpc(name = "eth_getTransactionByBlockNumberAndIndex")]
+    fn get_transaction_by_block_number_and_index(
+        &self,
+        block_number: String,
+        index: U256,
+    ) -> Result<Option<TransactionObject>>;
+
+    #[rpc(name = "eth_getTransactionByBlockHashAndIndex")]
+    fn get_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: H256,
+        index: U256,
+    ) -> Result<Option<TransactionObject>>;
+
+    #[rpc(name = "eth_pendingTransactions")]
+    fn pending_transactions(&self) -> Result<Vec<PoolTransactionObject>>;
+
     #[rpc(name = "eth_getTransactionReceipt")]
     fn get_transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>>;
 
@@ -31,7 +72,7 @@ pub trait EthRpc {
     fn get_transaction_count(
         &self,
         eth_address: String,
-        block_number: Option<String>,
+        block_number: Option<BlockParameter>,
     ) -> Result<U256>;
 
     #[rpc(name = "eth_getStorageAt")]
@@ -39,18 +80,93 @@ pub trait EthRpc {
         &self,
         eth_address: String,
         position: U256,
-        block_number: Option<String>,
+        block_number: Option<BlockParameter>,
     ) -> Result<H256>;
 
+    #[rpc(name = "eth_getCode")]
+    fn get_code(
+        &self,
+        eth_address: String,
+        block_number: Option<BlockParameter>,
+    ) -> Result<JsonBytes>;
+
     #[rpc(name = "eth_call")]
-    fn eth_call(&self, call: TransactionCall, block_number: Option<String>) -> Result<JsonBytes>;
+    fn eth_call(
+        &self,
+        call: TransactionCall,
+        block_number: Option<BlockParameter>,
+    ) -> Result<JsonBytes>;
+
+    #[rpc(name = "eth_estimateGas")]
+    fn estimate_gas(
+        &self,
+        call: TransactionCall,
+        block_number: Option<BlockParameter>,
+    ) -> Result<U256>;
+
+    #[rpc(name = "eth_getLogs")]
+    fn get_logs(&self, filter: LogFilter) -> Result<Vec<Log>>;
+
+    #[rpc(name = "eth_newFilter")]
+    fn new_filter(&self, filter: LogFilter) -> Result<U256>;
+
+    #[rpc(name = "eth_newBlockFilter")]
+    fn new_block_filter(&self) -> Result<U256>;
+
+    #[rpc(name = "eth_newPendingTransactionFilter")]
+    fn new_pending_transaction_filter(&self) -> Result<U256>;
+
+    #[rpc(name = "eth_getFilterChanges")]
+    fn get_filter_changes(&self, id: U256) -> Result<FilterChanges>;
+
+    #[rpc(name = "eth_getFilterLogs")]
+    fn get_filter_logs(&self, id: U256) -> Result<Vec<Log>>;
+
+    #[rpc(name = "eth_uninstallFilter")]
+    fn uninstall_filter(&self, id: U256) -> Result<bool>;
+
+    #[rpc(name = "eth_maxPriorityFeePerGas")]
+    fn max_priority_fee_per_gas(&self) -> Result<U256>;
+
+    #[rpc(name = "eth_feeHistory")]
+    fn fee_history(
+        &self,
+        block_count: String,
+        newest_block: String,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> Result<FeeHistory>;
 }
 
 pub struct EthRpcImpl {
     pub loader: Arc<Loader>,
+    pub txpool: Arc<TxPool>,
+    pub filters: Arc<Filters>,
+    // Whether transactions without EIP-155 replay protection are accepted
+    pub allow_unprotected_transactions: bool,
+}
+
+impl EthRpcImpl {
+    // Pending state includes transactions in local transaction pool
+    fn load_account(
+        &self,
+        eth_address: &EthAddress,
+        block_number: &Option<BlockParameter>,
+    ) -> std::result::Result<Option<EthAccount>, CrateError> {
+        match BlockNumber::from_parameter(block_number)? {
+            BlockNumber::Pending => self.txpool.load_pending_account(eth_address),
+            block_number => {
+                let block_number = self.loader.resolve_block_number(block_number)?;
+                self.loader.load_account(eth_address, block_number, true)
+            }
+        }
+    }
 }
 
 impl EthRpc for EthRpcImpl {
+    fn chain_id(&self) -> Result<String> {
+        Ok(serialize_u64(self.loader.chain_id))
+    }
+
     fn block_number(&self) -> Result<String> {
         Ok(serialize_u64(
             self.loader
@@ -59,12 +175,41 @@ impl EthRpc for EthRpcImpl {
         ))
     }
 
-    fn get_balance(&self, eth_address: String, block_number: Option<String>) -> Result<U256> {
-        let eth_address = EthAddress::parse(&eth_address)?;
-        let block_number = self
+    fn get_block_by_number(
+        &self,
+        block_number: String,
+        full_transactions: bool,
+    ) -> Result<Option<Block>> {
+        let block_number = match self
             .loader
-            .resolve_block_number(BlockNumber::parse_with_default(&block_number)?)?;
-        let account = self.loader.load_account(&eth_address, block_number, true)?;
+            .try_resolve_block_number(BlockNumber::parse(&block_number)?)?
+        {
+            Some(block_number) => block_number,
+            None => return Ok(None),
+        };
+        let block = self.loader.load_block(block_number, full_transactions)?;
+        Ok(block)
+    }
+
+    fn get_block_by_hash(
+        &self,
+        block_hash: H256,
+        full_transactions: bool,
+    ) -> Result<Option<Block>> {
+        let block = match self.loader.load_block_number(&block_hash)? {
+            Some(block_number) => self.loader.load_block(block_number, full_transactions)?,
+            None => None,
+        };
+        Ok(block)
+    }
+
+    fn get_balance(
+        &self,
+        eth_address: String,
+        block_number: Option<BlockParameter>,
+    ) -> Result<U256> {
+        let eth_address = EthAddress::parse(&eth_address)?;
+        let account = self.load_account(&eth_address, &block_number)?;
         let wei = match account {
             Some(account) => account.total_capacities_in_wei()?,
             None => U256::zero(),
@@ -75,13 +220,10 @@ impl EthRpc for EthRpcImpl {
     fn get_transaction_count(
         &self,
         eth_address: String,
-        block_number: Option<String>,
+        block_number: Option<BlockParameter>,
     ) -> Result<U256> {
         let eth_address = EthAddress::parse(&eth_address)?;
-        let block_number = self
-            .loader
-            .resolve_block_number(BlockNumber::parse_with_default(&block_number)?)?;
-        let account = self.loader.load_account(&eth_address, block_number, true)?;
+        let account = self.load_account(&eth_address, &block_number)?;
         let wei = match account {
             Some(account) => account.next_nonce()?,
             None => U256::zero(),
@@ -90,22 +232,64 @@ impl EthRpc for EthRpcImpl {
     }
 
     fn send_raw_transaction(&self, raw: JsonBytes) -> Result<H256> {
-        let tx = EthTransaction::from_raw(raw.into_bytes())?;
-        let block_number = self.loader.tip_block_number()?;
-        let ckb_transaction = Runner {
-            loader: &self.loader,
-            tx: &tx,
-            block_number,
+        let tx = EthTransaction::from_raw(raw.into_bytes(), self.loader.chain_id)?;
+        if tx.is_unprotected() && !self.allow_unprotected_transactions {
+            return Err(CrateError::MalformedData(
+                "Transactions without EIP-155 replay protection are not allowed!".to_string(),
+            )
+            .into());
         }
-        .run()?;
-        let tx_hash = self
+        let tx_hash = self.txpool.add(tx)?;
+        Ok(tx_hash)
+    }
+
+    fn get_transaction_by_hash(&self, hash: H256) -> Result<Option<TransactionObject>> {
+        let transaction = self.loader.load_transaction(&hash)?;
+        Ok(transaction)
+    }
+
+    fn get_transaction_by_block_number_and_index(
+        &self,
+        block_number: String,
+        index: U256,
+    ) -> Result<Option<TransactionObject>> {
+        let block_number = match self
+            .loader
+            .try_resolve_block_number(BlockNumber::parse(&block_number)?)?
+        {
+            Some(block_number) => block_number,
+            None => return Ok(None),
+        };
+        let transaction = self
             .loader
-            .ckb_client()
-            .send_transaction(ckb_transaction)
-            .call()
-            .map_err(|e| CrateError::Rpc(e.to_string()))?;
-        debug!("Sent CKB transaction: {:x}", tx_hash);
-        Ok(tx.hash())
+            .load_transaction_by_index(block_number, &index)?;
+        Ok(transaction)
+    }
+
+    fn get_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: H256,
+        index: U256,
+    ) -> Result<Option<TransactionObject>> {
+        let transaction = match self.loader.load_block_number(&block_hash)? {
+            Some(block_number) => self
+                .loader
+                .load_transaction_by_index(block_number, &index)?,
+            None => None,
+        };
+        Ok(transaction)
+    }
+
+    // Transactions sent to CKB but not yet committed
+    fn pending_transactions(&self) -> Result<Vec<PoolTransactionObject>> {
+        let transactions = self
+            .txpool
+            .content()?
+            .pending
+            .into_iter()
+            .flat_map(|(_, transactions)| transactions.into_iter().map(|(_, t)| t))
+            .collect();
+        Ok(transactions)
     }
 
     fn get_transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
@@ -117,18 +301,14 @@ impl EthRpc for EthRpcImpl {
         &self,
         eth_address: String,
         position: U256,
-        block_number: Option<String>,
+        block_number: Option<BlockParameter>,
     ) -> Result<H256> {
         let eth_address = EthAddress::parse(&eth_address)?;
-        let block_number = self
-            .loader
-            .resolve_block_number(BlockNumber::parse_with_default(&block_number)?)?;
-        let account = self
-            .loader
-            .load_account(&eth_address, block_number, true)?
-            .ok_or(CrateError::MalformedData(
-                "Contract does not exist!".to_string(),
-            ))?;
+        let account =
+            self.load_account(&eth_address, &block_number)?
+                .ok_or(CrateError::MalformedData(
+                    "Contract does not exist!".to_string(),
+                ))?;
         if !account.contract_account()? {
             return Err(CrateError::MalformedData(
                 "Specified account is not a contract!".to_string(),
@@ -144,17 +324,107 @@ impl EthRpc for EthRpcImpl {
         Ok(value.to_be_bytes().into())
     }
 
-    fn eth_call(&self, call: TransactionCall, block_number: Option<String>) -> Result<JsonBytes> {
+    fn get_code(
+        &self,
+        eth_address: String,
+        block_number: Option<BlockParameter>,
+    ) -> Result<JsonBytes> {
+        let eth_address = EthAddress::parse(&eth_address)?;
+        let code = match self.load_account(&eth_address, &block_number)? {
+            Some(ref account) if account.contract_account()? => account.contract_data()?.code,
+            _ => Bytes::new(),
+        };
+        Ok(JsonBytes::from_bytes(code))
+    }
+
+    fn eth_call(
+        &self,
+        call: TransactionCall,
+        block_number: Option<BlockParameter>,
+    ) -> Result<JsonBytes> {
         let tx = EthTransaction::try_from(call)?;
         let block_number = self
             .loader
-            .resolve_block_number(BlockNumber::parse_with_default(&block_number)?)?;
-        let result = Runner {
-            loader: &self.loader,
-            tx: &tx,
-            block_number,
-        }
-        .call()?;
+            .resolve_block_number(BlockNumber::from_parameter(&block_number)?)?;
+        let result = Runner::new(&self.loader, &tx, block_number).call()?;
         Ok(JsonBytes::from_bytes(result))
     }
+
+    fn estimate_gas(
+        &self,
+        call: TransactionCall,
+        block_number: Option<BlockParameter>,
+    ) -> Result<U256> {
+        let tx = EthTransaction::try_from(call)?;
+        let block_number = self
+            .loader
+            .resolve_block_number(BlockNumber::from_parameter(&block_number)?)?;
+        let gas = Runner::new(&self.loader, &tx, block_number).estimate_gas()?;
+        Ok(gas)
+    }
+
+    fn get_logs(&self, filter: LogFilter) -> Result<Vec<Log>> {
+        let (from_block, to_block) = filter.block_range(&self.loader)?;
+        let mut logs = vec![];
+        for block_number in from_block..=to_block {
+            logs.extend(
+                self.loader
+                    .load_logs(block_number)?
+                    .into_iter()
+                    .filter(|log| filter.matches(log)),
+            );
+        }
+        Ok(logs)
+    }
+
+    fn new_filter(&self, filter: LogFilter) -> Result<U256> {
+        let id = self.filters.new_log_filter(filter)?;
+        Ok(id)
+    }
+
+    fn new_block_filter(&self) -> Result<U256> {
+        let id = self.filters.new_block_filter()?;
+        Ok(id)
+    }
+
+    fn new_pending_transaction_filter(&self) -> Result<U256> {
+        let id = self.filters.new_pending_transaction_filter()?;
+        Ok(id)
+    }
+
+    fn get_filter_changes(&self, id: U256) -> Result<FilterChanges> {
+        let changes = self.filters.changes(&id)?;
+        Ok(changes)
+    }
+
+    fn get_filter_logs(&self, id: U256) -> Result<Vec<Log>> {
+        let logs = self.filters.logs(&id)?;
+        Ok(logs)
+    }
+
+    fn uninstall_filter(&self, id: U256) -> Result<bool> {
+        let uninstalled = self.filters.uninstall(&id)?;
+        Ok(uninstalled)
+    }
+
+    fn max_priority_fee_per_gas(&self) -> Result<U256> {
+        let priority_fee = self.loader.suggest_priority_fee()?;
+        Ok(priority_fee)
+    }
+
+    fn fee_history(
+        &self,
+        block_count: String,
+        newest_block: String,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> Result<FeeHistory> {
+        let block_count = parse_u64(&block_count)?;
+        let newest_block = self
+            .loader
+            .resolve_block_number(BlockNumber::parse(&newest_block)?)?;
+        let fee_history =
+            self.loader
+                .load_fee_history(block_count, newest_block, &reward_percentiles)?;
+        Ok(fee_history)
+    }
 }
@@ -0,0 +1,258 @@
+use super::LogFilter;
+use crate::{
+    storage::{u256_to_u64, BlockNumber, Loader, Log, TxPool},
+    Error,
+};
+use numext_fixed_hash::H256;
+use numext_fixed_uint::U256;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Filters that are not polled within this period are removed
+const FILTER_TIMEOUT: Duration = Duration::from_secs(300);
+// Number of recent block hashes kept by each filter to detect reverts
+const MAX_RECENT_BLOCKS: usize = 64;
+
+enum FilterKind {
+    Block,
+    // Hashes of pending transactions returned in last poll
+    PendingTransaction(HashSet<H256>),
+    Log(LogFilter),
+}
+
+struct Filter {
+    kind: FilterKind,
+    // Last block processed by the filter
+    cursor: u64,
+    recent_blocks: VecDeque<(u64, H256)>,
+}
+
+impl Filter {
+    // Moves cursor back to the last block that is still indexed, blocks
+    // reverted by the indexer will be processed again once re-indexed.
+    fn rewind(&mut self, loader: &Loader) -> Result<(), Error> {
+        while let Some((block_number, block_hash)) = self.recent_blocks.back().cloned() {
+            if loader.load_block_hash(block_number)? == Some(block_hash) {
+                break;
+            }
+            debug!("rewinding filter due to reverted block {}", block_number);
+            self.recent_blocks.pop_back();
+            self.cursor = block_number - 1;
+        }
+        Ok(())
+    }
+
+    // Returns newly indexed blocks and moves cursor to indexed tip
+    fn advance(&mut self, loader: &Loader) -> Result<Vec<(u64, H256)>, Error> {
+        self.rewind(loader)?;
+        let tip = loader.tip_block_number()?;
+        let mut blocks = vec![];
+        for block_number in (self.cursor + 1)..=tip {
+            let block_hash = match loader.load_block_hash(block_number)? {
+                Some(block_hash) => block_hash,
+                None => break,
+            };
+            self.cursor = block_number;
+            self.recent_blocks
+                .push_back((block_number, block_hash.clone()));
+            if self.recent_blocks.len() > MAX_RECENT_BLOCKS {
+                self.recent_blocks.pop_front();
+            }
+            blocks.push((block_number, block_hash));
+        }
+        Ok(blocks)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FilterChanges {
+    Hashes(Vec<H256>),
+    Logs(Vec<Log>),
+}
+
+// Each filter has its own lock, so polling a filter only holds the registry
+// lock while looking the filter up, not while reading indexed data.
+struct FilterEntry {
+    filter: Arc<Mutex<Filter>>,
+    last_polled: Instant,
+}
+
+#[derive(Default)]
+struct FilterRegistry {
+    next_id: u64,
+    filters: HashMap<u64, FilterEntry>,
+}
+
+pub struct Filters {
+    loader: Arc<Loader>,
+    txpool: Arc<TxPool>,
+    registry: Mutex<FilterRegistry>,
+}
+
+impl Filters {
+    pub fn new(loader: Arc<Loader>, txpool: Arc<TxPool>) -> Self {
+        Filters {
+            loader,
+            txpool,
+            registry: Mutex::new(FilterRegistry::default()),
+        }
+    }
+
+    pub fn new_block_filter(&self) -> Result<U256, Error> {
+        self.install(FilterKind::Block)
+    }
+
+    pub fn new_pending_transaction_filter(&self) -> Result<U256, Error> {
+        let hashes = self.txpool.pending_hashes()?.into_iter().collect();
+        self.install(FilterKind::PendingTransaction(hashes))
+    }
+
+    pub fn new_log_filter(&self, filter: LogFilter) -> Result<U256, Error> {
+        if filter.block_hash.is_some() {
+            return Err(Error::MalformedData(
+                "blockHash is only supported by eth_getLogs!".to_string(),
+            ));
+        }
+        BlockNumber::parse_with_default(&filter.from_block)?;
+        BlockNumber::parse_with_default(&filter.to_block)?;
+        self.install(FilterKind::Log(filter))
+    }
+
+    pub fn uninstall(&self, id: &U256) -> Result<bool, Error> {
+        let mut registry = self.lock()?;
+        Ok(registry.filters.remove(&u256_to_u64(id)?).is_some())
+    }
+
+    // Changes since last poll
+    pub fn changes(&self, id: &U256) -> Result<FilterChanges, Error> {
+        let entry = self.poll(id)?;
+        let mut filter = entry
+            .lock()
+            .map_err(|_| Error::MalformedData("Filter is poisoned!".to_string()))?;
+        let blocks = filter.advance(&self.loader)?;
+        match &mut filter.kind {
+            FilterKind::Block => Ok(FilterChanges::Hashes(
+                blocks
+                    .into_iter()
+                    .map(|(_, block_hash)| block_hash)
+                    .collect(),
+            )),
+            FilterKind::PendingTransaction(seen_hashes) => {
+                let hashes: HashSet<H256> = self.txpool.pending_hashes()?.into_iter().collect();
+                let new_hashes = hashes.difference(seen_hashes).cloned().collect();
+                *seen_hashes = hashes;
+                Ok(FilterChanges::Hashes(new_hashes))
+            }
+            FilterKind::Log(log_filter) => {
+                let (from_block, to_block) = fixed_block_range(log_filter)?;
+                let mut logs = vec![];
+                for (block_number, _) in blocks {
+                    if from_block.map(|n| block_number < n).unwrap_or(false)
+                        || to_block.map(|n| block_number > n).unwrap_or(false)
+                    {
+                        continue;
+                    }
+                    logs.extend(
+                        self.loader
+                            .load_logs(block_number)?
+                            .into_iter()
+                            .filter(|log| log_filter.matches(log)),
+                    );
+                }
+                Ok(FilterChanges::Logs(logs))
+            }
+        }
+    }
+
+    // All logs matching a log filter
+    pub fn logs(&self, id: &U256) -> Result<Vec<Log>, Error> {
+        let log_filter = match &self
+            .poll(id)?
+            .lock()
+            .map_err(|_| Error::MalformedData("Filter is poisoned!".to_string()))?
+            .kind
+        {
+            FilterKind::Log(log_filter) => log_filter.clone(),
+            _ => {
+                return Err(Error::MalformedData(
+                    "Filter is not a log filter!".to_string(),
+                ))
+            }
+        };
+        // Same range limit as eth_getLogs applies
+        let (from_block, to_block) = log_filter.block_range(&self.loader)?;
+        let mut logs = vec![];
+        for block_number in from_block..=to_block {
+            logs.extend(
+                self.loader
+                    .load_logs(block_number)?
+                    .into_iter()
+                    .filter(|log| log_filter.matches(log)),
+            );
+        }
+        Ok(logs)
+    }
+
+    fn install(&self, kind: FilterKind) -> Result<U256, Error> {
+        let tip = self.loader.tip_block_number()?;
+        let mut recent_blocks = VecDeque::new();
+        if let Some(block_hash) = self.loader.load_block_hash(tip)? {
+            recent_blocks.push_back((tip, block_hash));
+        }
+        let mut registry = self.lock()?;
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.filters.insert(
+            id,
+            FilterEntry {
+                filter: Arc::new(Mutex::new(Filter {
+                    kind,
+                    cursor: tip,
+                    recent_blocks,
+                })),
+                last_polled: Instant::now(),
+            },
+        );
+        Ok(U256::from(id))
+    }
+
+    // Looks up a filter and marks it as polled
+    fn poll(&self, id: &U256) -> Result<Arc<Mutex<Filter>>, Error> {
+        let mut registry = self.lock()?;
+        let entry = registry
+            .filters
+            .get_mut(&u256_to_u64(id)?)
+            .ok_or(Error::MalformedData("Filter not found!".to_string()))?;
+        entry.last_polled = Instant::now();
+        Ok(Arc::clone(&entry.filter))
+    }
+
+    // Expired filters are removed each time the registry is accessed
+    fn lock(&self) -> Result<MutexGuard<FilterRegistry>, Error> {
+        let mut registry = self
+            .registry
+            .lock()
+            .map_err(|_| Error::MalformedData("Filter registry is poisoned!".to_string()))?;
+        registry
+            .filters
+            .retain(|_, entry| entry.last_polled.elapsed() < FILTER_TIMEOUT);
+        Ok(registry)
+    }
+}
+
+// Fixed block range of a log filter, tags like "latest" follow the indexed
+// tip and are returned as None.
+fn fixed_block_range(filter: &LogFilter) -> Result<(Option<u64>, Option<u64>), Error> {
+    let fixed = |block_number| match block_number {
+        BlockNumber::Number(n) => Some(n),
+        BlockNumber::Earliest => Some(0),
+        _ => None,
+    };
+    Ok((
+        fixed(BlockNumber::parse_with_default(&filter.from_block)?),
+        fixed(BlockNumber::parse_with_default(&filter.to_block)?),
+    ))
+}
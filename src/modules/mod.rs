@@ -1,40 +1,82 @@
 mod eth;
+mod filter;
+mod net;
+mod pubsub;
+mod txpool;
 mod web3;
 
 use crate::{
-    storage::{EthAddress, EthTransaction},
+    storage::{
+        AccessListItem, BlockNumber, EthAddress, EthTransaction, Loader, Log,
+        ACCESS_LIST_TRANSACTION_TYPE, BLOCK_GAS_LIMIT, DYNAMIC_FEE_TRANSACTION_TYPE,
+        LEGACY_TRANSACTION_TYPE,
+    },
     Error,
 };
 use bytes::Bytes;
 use ckb_jsonrpc_types::JsonBytes;
+use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use serde_derive::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
 pub use eth::{EthRpc, EthRpcImpl};
+pub use filter::{FilterChanges, Filters};
+pub use net::{NetRpc, NetRpcImpl};
+pub use pubsub::{EthPubSub, EthPubSubImpl, SubscriptionResult};
+pub use txpool::{TxPoolRpc, TxPoolRpcImpl};
 pub use web3::{Web3Rpc, Web3RpcImpl};
 
+// Maximum number of blocks scanned by a single log query
+pub const MAX_LOG_BLOCK_RANGE: u64 = 10_000;
+
 #[derive(Serialize, Deserialize)]
 pub struct TransactionCall {
     pub from: Option<String>,
-    pub to: String,
+    pub to: Option<String>,
     pub gas: Option<U256>,
     pub gas_price: Option<U256>,
     pub value: Option<U256>,
     pub data: Option<JsonBytes>,
+    #[serde(rename = "maxFeePerGas")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(rename = "maxPriorityFeePerGas")]
+    pub max_priority_fee_per_gas: Option<U256>,
+    #[serde(rename = "accessList")]
+    pub access_list: Option<Vec<AccessListItem>>,
 }
 
 impl TryFrom<TransactionCall> for EthTransaction {
     type Error = Error;
 
     fn try_from(call: TransactionCall) -> Result<Self, Self::Error> {
+        let transaction_type =
+            if call.max_fee_per_gas.is_some() || call.max_priority_fee_per_gas.is_some() {
+                DYNAMIC_FEE_TRANSACTION_TYPE
+            } else if call.access_list.is_some() {
+                ACCESS_LIST_TRANSACTION_TYPE
+            } else {
+                LEGACY_TRANSACTION_TYPE
+            };
+        let gas_price = call
+            .max_fee_per_gas
+            .or(call.gas_price)
+            .unwrap_or(U256::one());
         Ok(EthTransaction {
+            transaction_type,
             nonce: 0,
-            gas_price: call.gas_price.unwrap_or(U256::one()),
-            gas_limit: call.gas.unwrap_or(U256::max_value()),
-            to: Some(EthAddress::parse(&call.to)?),
+            max_priority_fee_per_gas: call
+                .max_priority_fee_per_gas
+                .unwrap_or_else(|| gas_price.clone()),
+            gas_price,
+            gas_limit: call.gas.unwrap_or_else(|| BLOCK_GAS_LIMIT.into()),
+            to: match call.to {
+                Some(to) => Some(EthAddress::parse(&to)?),
+                None => None,
+            },
             value: call.value.unwrap_or(U256::zero()),
             data: call.data.map(|data| data.into_bytes()),
+            access_list: call.access_list.unwrap_or_default(),
             v: 0,
             r: U256::zero(),
             s: U256::zero(),
@@ -46,3 +88,88 @@ impl TryFrom<TransactionCall> for EthTransaction {
         })
     }
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            OneOrMany::One(value) => std::slice::from_ref(value),
+            OneOrMany::Many(values) => &values,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LogFilter {
+    #[serde(rename = "fromBlock")]
+    pub from_block: Option<String>,
+    #[serde(rename = "toBlock")]
+    pub to_block: Option<String>,
+    // EIP-234, restricts the query to a single block
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<H256>,
+    pub address: Option<OneOrMany<JsonBytes>>,
+    pub topics: Option<Vec<Option<OneOrMany<H256>>>>,
+}
+
+impl LogFilter {
+    // Inclusive range of blocks to scan for a one-off query
+    pub fn block_range(&self, loader: &Loader) -> Result<(u64, u64), Error> {
+        if let Some(block_hash) = &self.block_hash {
+            if self.from_block.is_some() || self.to_block.is_some() {
+                return Err(Error::MalformedData(
+                    "blockHash cannot be used together with fromBlock or toBlock!".to_string(),
+                ));
+            }
+            let block_number = loader
+                .load_block_number(block_hash)?
+                .ok_or(Error::MalformedData("Block not found!".to_string()))?;
+            return Ok((block_number, block_number));
+        }
+        // Blocks ahead of the indexed tip have no logs yet, so the range is
+        // clamped to the tip and might be empty.
+        let tip = loader.tip_block_number()?;
+        let from_block = loader
+            .try_resolve_block_number(BlockNumber::parse_with_default(&self.from_block)?)?
+            .unwrap_or(tip + 1);
+        let to_block = loader
+            .try_resolve_block_number(BlockNumber::parse_with_default(&self.to_block)?)?
+            .unwrap_or(tip);
+        if to_block >= from_block && to_block - from_block >= MAX_LOG_BLOCK_RANGE {
+            return Err(Error::MalformedData(format!(
+                "Block range exceeds the limit of {} blocks!",
+                MAX_LOG_BLOCK_RANGE
+            )));
+        }
+        Ok((from_block, to_block))
+    }
+
+    pub fn matches(&self, log: &Log) -> bool {
+        if let Some(address) = &self.address {
+            if !address
+                .as_slice()
+                .iter()
+                .any(|address| address.as_bytes() == log.address.as_bytes())
+            {
+                return false;
+            }
+        }
+        if let Some(topics) = &self.topics {
+            for (i, topic) in topics.iter().enumerate() {
+                if let Some(topic) = topic {
+                    match log.topics.get(i) {
+                        Some(log_topic) if topic.as_slice().contains(log_topic) => (),
+                        _ => return false,
+                    }
+                }
+            }
+        }
+        true
+    }
+}
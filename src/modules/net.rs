@@ -0,0 +1,45 @@
+use crate::{
+    storage::{serialize_u64, Loader},
+    Error as CrateError,
+};
+use jsonrpc_core::Result;
+use jsonrpc_derive::rpc;
+use std::sync::Arc;
+
+#[rpc]
+pub trait NetRpc {
+    #[rpc(name = "net_version")]
+    fn version(&self) -> Result<String>;
+
+    #[rpc(name = "net_listening")]
+    fn listening(&self) -> Result<bool>;
+
+    #[rpc(name = "net_peerCount")]
+    fn peer_count(&self) -> Result<String>;
+}
+
+pub struct NetRpcImpl {
+    pub loader: Arc<Loader>,
+}
+
+impl NetRpc for NetRpcImpl {
+    fn version(&self) -> Result<String> {
+        Ok(self.loader.chain_id.to_string())
+    }
+
+    fn listening(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    // Polyjuice has no peers of its own, peers of the underlying CKB node
+    // are reported instead.
+    fn peer_count(&self) -> Result<String> {
+        let peers = self
+            .loader
+            .ckb_client()
+            .get_peers()
+            .call()
+            .map_err(|e| CrateError::Rpc(e.to_string()))?;
+        Ok(serialize_u64(peers.0.len() as u64))
+    }
+}
@@ -0,0 +1,231 @@
+use super::LogFilter;
+use crate::storage::{Block, IndexerEvent, Loader, Log};
+use futures::executor::{spawn, Notify, NotifyHandle};
+use jsonrpc_core::{Error, Result};
+use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{
+    typed::{Sink, Subscriber},
+    Session, SubscriptionId,
+};
+use numext_fixed_hash::H256;
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+pub enum SubscriptionResult {
+    Header(Block),
+    Log(Log),
+    TransactionHash(H256),
+}
+
+#[rpc]
+pub trait EthPubSub {
+    type Metadata;
+
+    #[pubsub(subscription = "eth_subscription", subscribe, name = "eth_subscribe")]
+    fn subscribe(
+        &self,
+        meta: Self::Metadata,
+        subscriber: Subscriber<SubscriptionResult>,
+        kind: String,
+        filter: Option<LogFilter>,
+    );
+
+    #[pubsub(
+        subscription = "eth_subscription",
+        unsubscribe,
+        name = "eth_unsubscribe"
+    )]
+    fn unsubscribe(&self, meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+#[derive(Default)]
+struct SubscriptionRegistry {
+    next_id: u64,
+    new_heads: HashMap<SubscriptionId, Sink<SubscriptionResult>>,
+    logs: HashMap<SubscriptionId, (Sink<SubscriptionResult>, LogFilter)>,
+    pending_transactions: HashMap<SubscriptionId, Sink<SubscriptionResult>>,
+}
+
+#[derive(Clone)]
+pub struct EthPubSubImpl {
+    loader: Arc<Loader>,
+    registry: Arc<Mutex<SubscriptionRegistry>>,
+}
+
+impl EthPubSubImpl {
+    pub fn new(loader: Arc<Loader>) -> Self {
+        EthPubSubImpl {
+            loader,
+            registry: Arc::new(Mutex::new(SubscriptionRegistry::default())),
+        }
+    }
+
+    // Notifies subscribers of indexed and reverted blocks, this returns when
+    // indexer stops. The caller is responsible for wrapping it into a
+    // separate thread.
+    pub fn notify_indexer_events(&self, events: Receiver<IndexerEvent>) {
+        for event in events {
+            let (headers, logs) = match event {
+                IndexerEvent::BlockIndexed(block_number) => {
+                    let header = match self.loader.load_block(block_number, false) {
+                        Ok(header) => header,
+                        Err(e) => {
+                            warn!("Failed to load block {}: {:?}", block_number, e);
+                            None
+                        }
+                    };
+                    let logs = self.loader.load_logs(block_number).unwrap_or_else(|e| {
+                        warn!("Failed to load logs in block {}: {:?}", block_number, e);
+                        vec![]
+                    });
+                    (header.into_iter().collect(), logs)
+                }
+                IndexerEvent::BlockReverted(_, logs) => (vec![], logs),
+            };
+            let (new_heads, log_subscriptions) = {
+                let registry = self.registry.lock().expect("locking");
+                (
+                    clone_sinks(&registry.new_heads),
+                    registry
+                        .logs
+                        .iter()
+                        .map(|(id, (sink, filter))| (id.clone(), sink.clone(), filter.clone()))
+                        .collect::<Vec<_>>(),
+                )
+            };
+            let mut closed = HashSet::new();
+            for header in headers {
+                notify(&new_heads, &SubscriptionResult::Header(header), &mut closed);
+            }
+            for log in logs {
+                let result = SubscriptionResult::Log(log.clone());
+                for (id, sink, filter) in &log_subscriptions {
+                    if filter.matches(&log) && !try_notify(sink, &result) {
+                        closed.insert(id.clone());
+                    }
+                }
+            }
+            self.remove_closed(&closed);
+        }
+    }
+
+    // Notifies subscribers of transactions sent to CKB by local transaction
+    // pool, this returns when the pool is dropped.
+    pub fn notify_pending_transactions(&self, hashes: Receiver<H256>) {
+        for hash in hashes {
+            let sinks = clone_sinks(&self.registry.lock().expect("locking").pending_transactions);
+            let mut closed = HashSet::new();
+            notify(
+                &sinks,
+                &SubscriptionResult::TransactionHash(hash),
+                &mut closed,
+            );
+            self.remove_closed(&closed);
+        }
+    }
+
+    // Subscriptions whose sessions are closed are removed
+    fn remove_closed(&self, closed: &HashSet<SubscriptionId>) {
+        if closed.is_empty() {
+            return;
+        }
+        let mut registry = self.registry.lock().expect("locking");
+        for id in closed {
+            registry.new_heads.remove(id);
+            registry.logs.remove(id);
+            registry.pending_transactions.remove(id);
+        }
+    }
+}
+
+// Sinks are cloned out of the registry so notifications are sent without
+// holding the lock.
+fn clone_sinks(
+    sinks: &HashMap<SubscriptionId, Sink<SubscriptionResult>>,
+) -> Vec<(SubscriptionId, Sink<SubscriptionResult>)> {
+    sinks
+        .iter()
+        .map(|(id, sink)| (id.clone(), sink.clone()))
+        .collect()
+}
+
+fn notify(
+    sinks: &[(SubscriptionId, Sink<SubscriptionResult>)],
+    result: &SubscriptionResult,
+    closed: &mut HashSet<SubscriptionId>,
+) {
+    for (id, sink) in sinks {
+        if !try_notify(sink, result) {
+            closed.insert(id.clone());
+        }
+    }
+}
+
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+// Polls the send once instead of waiting on it. The sink sends through a
+// fresh clone of the transport, which queues the message right away even if
+// the client falls behind, so NotReady only means the buffer is exceeded
+// and waiting would stall other subscribers. Returns false when the session
+// is closed.
+fn try_notify(sink: &Sink<SubscriptionResult>, result: &SubscriptionResult) -> bool {
+    let notify_handle = NotifyHandle::from(Arc::new(NoopNotify));
+    spawn(sink.notify(Ok(result.clone())))
+        .poll_future_notify(&notify_handle, 0)
+        .is_ok()
+}
+
+impl EthPubSub for EthPubSubImpl {
+    type Metadata = Option<Arc<Session>>;
+
+    fn subscribe(
+        &self,
+        _meta: Self::Metadata,
+        subscriber: Subscriber<SubscriptionResult>,
+        kind: String,
+        filter: Option<LogFilter>,
+    ) {
+        let mut registry = self.registry.lock().expect("locking");
+        registry.next_id += 1;
+        let id = SubscriptionId::String(format!("0x{:x}", registry.next_id));
+        match kind.as_str() {
+            "newHeads" => {
+                if let Ok(sink) = subscriber.assign_id(id.clone()) {
+                    registry.new_heads.insert(id, sink);
+                }
+            }
+            "logs" => {
+                if let Ok(sink) = subscriber.assign_id(id.clone()) {
+                    registry.logs.insert(id, (sink, filter.unwrap_or_default()));
+                }
+            }
+            "newPendingTransactions" => {
+                if let Ok(sink) = subscriber.assign_id(id.clone()) {
+                    registry.pending_transactions.insert(id, sink);
+                }
+            }
+            _ => {
+                let _ = subscriber.reject(Error::invalid_params(format!(
+                    "Unsupported subscription: {}",
+                    kind
+                )));
+            }
+        }
+    }
+
+    fn unsubscribe(&self, _meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+        let mut registry = self.registry.lock().expect("locking");
+        let removed = registry.new_heads.remove(&id).is_some()
+            || registry.logs.remove(&id).is_some()
+            || registry.pending_transactions.remove(&id).is_some();
+        Ok(removed)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::storage::{
+    PoolTransactionObject, TransactionObject, TxPool, TxPoolContent, TxPoolStatus,
+};
+use faster_hex::hex_string;
+use jsonrpc_core::Result;
+use jsonrpc_derive::rpc;
+use numext_fixed_uint::U256;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[rpc]
+pub trait TxPoolRpc {
+    #[rpc(name = "txpool_status")]
+    fn status(&self) -> Result<TxPoolStatus>;
+
+    #[rpc(name = "txpool_content")]
+    fn content(&self) -> Result<TxPoolContent<PoolTransactionObject>>;
+
+    #[rpc(name = "txpool_inspect")]
+    fn inspect(&self) -> Result<TxPoolContent<String>>;
+}
+
+pub struct TxPoolRpcImpl {
+    pub txpool: Arc<TxPool>,
+}
+
+impl TxPoolRpc for TxPoolRpcImpl {
+    fn status(&self) -> Result<TxPoolStatus> {
+        let content = self.txpool.content()?;
+        let count = |transactions: &BTreeMap<String, BTreeMap<u64, PoolTransactionObject>>| {
+            U256::from(transactions.values().map(|t| t.len() as u64).sum::<u64>())
+        };
+        Ok(TxPoolStatus {
+            pending: count(&content.pending),
+            queued: count(&content.queued),
+        })
+    }
+
+    fn content(&self) -> Result<TxPoolContent<PoolTransactionObject>> {
+        let content = self.txpool.content()?;
+        Ok(content)
+    }
+
+    fn inspect(&self) -> Result<TxPoolContent<String>> {
+        let content = self.txpool.content()?;
+        Ok(TxPoolContent {
+            pending: summarize(content.pending),
+            queued: summarize(content.queued),
+            rejected: summarize(content.rejected),
+        })
+    }
+}
+
+fn summarize(
+    transactions: BTreeMap<String, BTreeMap<u64, PoolTransactionObject>>,
+) -> BTreeMap<String, BTreeMap<u64, String>> {
+    transactions
+        .into_iter()
+        .map(|(address, transactions)| {
+            let summaries = transactions
+                .into_iter()
+                .map(|(nonce, t)| (nonce, summarize_transaction(&t.transaction)))
+                .collect();
+            (address, summaries)
+        })
+        .collect()
+}
+
+// Same format as Geth: "<to>: <value> wei + <gas> gas × <gas price> wei"
+fn summarize_transaction(transaction: &TransactionObject) -> String {
+    let to = match &transaction.to {
+        Some(to) => format!("0x{}", hex_string(to.as_bytes()).expect("hex encode")),
+        None => "contract creation".to_string(),
+    };
+    format!(
+        "{}: {} wei + {} gas × {} wei",
+        to, transaction.value, transaction.gas, transaction.gas_price
+    )
+}
@@ -1,9 +1,11 @@
 use super::{
-    build_block_added_out_points_key, build_block_hash_key, build_block_receipt_hashes_key,
-    build_block_spent_out_points_key, build_eth_key, build_out_point_key, build_receipt_key,
-    load_latest_out_points, Error, EthAddress, EthBasicReceipt, EthTransaction, BLOCK_KEY,
+    build_block_added_out_points_key, build_block_hash_key, build_block_number_key,
+    build_block_receipt_hashes_key, build_block_spent_out_points_key, build_eth_key,
+    build_out_point_key, build_receipt_key, extract_execution_result, extract_lock_address,
+    load_latest_out_points, Error, EthAddress, EthBasicReceipt, EthExecutionResult, EthTransaction,
+    Log, BLOCK_KEY,
 };
-use crate::{CODE_HASH_CONTRACT_LOCK, CODE_HASH_LOCK};
+use crate::CODE_HASH_LOCK;
 use bincode::{deserialize, serialize};
 use bytes::Bytes;
 use ckb_core::transaction::Witness;
@@ -14,23 +16,51 @@ use numext_fixed_uint::U256;
 use rocksdb::{WriteBatch, DB};
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
+#[derive(Clone, Debug)]
+pub enum IndexerEvent {
+    BlockIndexed(u64),
+    // Block reverted due to fork, together with logs in the block, which
+    // are marked as removed.
+    BlockReverted(u64, Vec<Log>),
+}
+
 pub struct Indexer {
     pub db: Arc<DB>,
     pub client: HttpRpcClient,
+    pub chain_id: u64,
+    // Time to wait when no new block is available
+    pub poll_interval: Duration,
+    subscribers: Vec<Sender<IndexerEvent>>,
 }
 
 impl Indexer {
-    pub fn from(db: Arc<DB>, ckb_uri: &str) -> Self {
+    pub fn from(db: Arc<DB>, ckb_uri: &str, chain_id: u64) -> Self {
         Indexer {
             db,
             client: HttpRpcClient::from_uri(ckb_uri),
+            chain_id,
+            poll_interval: Duration::from_secs(3),
+            subscribers: vec![],
         }
     }
 
+    // Events are sent after changes of the block are written to DB
+    pub fn subscribe(&mut self) -> Receiver<IndexerEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn notify(&mut self, event: IndexerEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
     // Ideally this should never return. The caller is responsible for wrapping
     // it into a separate thread.
     pub fn index(&mut self) -> Result<(), Error> {
@@ -56,6 +86,9 @@ impl Indexer {
                         // There is a fork, revert current block and start
                         // a new loop iteration.
                         let mut batch = WriteBatch::default();
+                        let reverted_block_hash = H256::from_slice(&block_hash).map_err(|e| {
+                            Error::MalformedData(format!("Invalid block hash: {:?}", e))
+                        })?;
                         let receipt_hashes_key = build_block_receipt_hashes_key(block_number);
                         let receipt_hashes: Vec<H256> = deserialize(
                             self.db
@@ -66,10 +99,23 @@ impl Indexer {
                                 .as_ref(),
                         )?;
                         batch.delete(&receipt_hashes_key)?;
+                        let mut reverted_logs = vec![];
                         for receipt_hash in &receipt_hashes {
                             let key = build_receipt_key(&receipt_hash);
+                            if let Some(data) = self.db.get(&key)? {
+                                let receipt: EthBasicReceipt = deserialize(&data)?;
+                                reverted_logs.extend(
+                                    Log::from_receipt(&receipt, receipt_hash, &reverted_block_hash)
+                                        .into_iter()
+                                        .map(|mut log| {
+                                            log.removed = true;
+                                            log
+                                        }),
+                                );
+                            }
                             batch.delete(&key)?;
                         }
+                        reverted_logs.sort_by(|a, b| a.log_index.cmp(&b.log_index));
                         let added_out_points_key = build_block_added_out_points_key(block_number);
                         let added_out_points: Vec<CellOutPoint> = deserialize(
                             self.db
@@ -81,8 +127,28 @@ impl Indexer {
                         )?;
                         batch.delete(&added_out_points_key)?;
                         let spent_out_points_key = build_block_spent_out_points_key(block_number);
+                        let spent_out_points: Vec<CellOutPoint> = deserialize(
+                            self.db
+                                .get(&spent_out_points_key)?
+                                .ok_or(Error::MalformedData(
+                                    "Spent out point key does not exist!".to_string(),
+                                ))?
+                                .as_ref(),
+                        )?;
                         batch.delete(&spent_out_points_key)?;
+                        batch.delete(&build_block_number_key(&reverted_block_hash))?;
+                        batch.delete(&build_block_hash_key(block_number))?;
                         let mut eth_addresses: HashSet<EthAddress> = HashSet::new();
+                        // Accounts only spending cells in the block, such as
+                        // destroyed contracts, also have cells indexed at it.
+                        // Out point keys of spent cells are kept around.
+                        for out_point in &spent_out_points {
+                            let key = build_out_point_key(&out_point)?;
+                            let eth_address = self.db.get(&key)?.ok_or(Error::MalformedData(
+                                "Out point key does not exist!".to_string(),
+                            ))?;
+                            eth_addresses.insert(eth_address.as_ref().into());
+                        }
                         for out_point in &added_out_points {
                             let key = build_out_point_key(&out_point)?;
                             let eth_address = self.db.get(&key)?.ok_or(Error::MalformedData(
@@ -116,6 +182,7 @@ impl Indexer {
                             batch.delete(BLOCK_KEY)?;
                         }
                         self.db.write(batch)?;
+                        self.notify(IndexerEvent::BlockReverted(block_number, reverted_logs));
 
                         continue;
                     }
@@ -138,7 +205,9 @@ impl Indexer {
                     (HashSet<CellOutPoint>, HashSet<CellOutPoint>),
                 > = HashMap::default();
                 let mut receipts: HashMap<H256, EthBasicReceipt> = HashMap::default();
-                let mut current_transaction_index = 1;
+                // Transaction index starts at 0 as in Ethereum
+                let mut current_transaction_index = 0;
+                let mut current_log_index = 0;
                 let mut current_cumulated_gas = U256::zero();
                 // Process the block here.
                 for transaction in next_block.transactions {
@@ -148,31 +217,60 @@ impl Indexer {
                         .iter()
                         .any(|o| o.lock.code_hash.as_bytes() == CODE_HASH_LOCK)
                     {
+                        let mut execution_result =
+                            match extract_execution_result(&transaction.inner.witnesses) {
+                                Ok(execution_result) => execution_result,
+                                Err(e) => {
+                                    warn!(
+                                        "Skipping unparsable execution result in {:x}: {:?}",
+                                        transaction.hash, e
+                                    );
+                                    None
+                                }
+                            };
                         // Index Ethereum transactions for receipts
                         for (i, witness) in transaction.inner.witnesses.iter().enumerate() {
                             // TODO: when data is properly exposed, we don't need
                             // this.
                             let witness: Witness = witness.clone().into();
                             if witness.len() == 1 {
-                                let tx = match EthTransaction::from_raw(witness[0].clone()) {
+                                let tx = match EthTransaction::from_raw(
+                                    witness[0].clone(),
+                                    self.chain_id,
+                                ) {
                                     Ok(tx) => tx,
                                     Err(e) => {
                                         warn!("Skipping witness at {:x} {} since we cannot parse it: {:?}", transaction.hash, i, e);
                                         continue;
                                     }
                                 };
+                                // A CKB transaction carries at most one
+                                // execution result, it belongs to the Ethereum
+                                // transaction it contains.
+                                let EthExecutionResult { gas_used, logs } = execution_result
+                                    .take()
+                                    .unwrap_or_else(|| EthExecutionResult {
+                                        gas_used: tx.gas_limit.clone(),
+                                        logs: vec![],
+                                    });
                                 current_cumulated_gas =
-                                    current_cumulated_gas.checked_add(&tx.fees()?).ok_or(
-                                        Error::MalformedData("Wei addition overflow!".to_string()),
+                                    current_cumulated_gas.checked_add(&gas_used).ok_or(
+                                        Error::MalformedData("Gas addition overflow!".to_string()),
                                     )?;
+                                let log_index = current_log_index;
+                                current_log_index += logs.len() as u64;
                                 receipts.insert(
                                     tx.hash(),
                                     EthBasicReceipt {
                                         transaction_index: current_transaction_index,
                                         cumulative_gas: current_cumulated_gas.clone(),
+                                        gas_used,
+                                        effective_gas_price: tx.effective_gas_price(),
                                         witness_index: i as u64,
                                         ckb_transaction_hash: transaction.hash.clone(),
                                         block_number: next_block_number,
+                                        log_index,
+                                        logs,
                                     },
                                 );
                                 current_transaction_index += 1;
@@ -184,7 +282,6 @@ impl Indexer {
                     for input in transaction.inner.inputs {
                         if let Some(cell_out_point) = &input.previous_output.cell {
                             let cell_out_point_key = build_out_point_key(&cell_out_point)?;
-;
                             if let Some(eth_address) = self.db.get(&cell_out_point_key)? {
                                 diff_cells
                                     .entry(eth_address.as_ref().into())
@@ -201,17 +298,13 @@ impl Indexer {
                     }
 
                     for (i, output) in transaction.inner.outputs.iter().enumerate() {
-                        if (output.lock.code_hash.as_bytes() == CODE_HASH_LOCK
-                            || output.lock.code_hash.as_bytes() == CODE_HASH_CONTRACT_LOCK)
-                            && output.lock.args.len() == 1
-                            && output.lock.args[0].len() == 20
+                        if let Some(eth_address) = extract_lock_address(&output.lock, self.chain_id)
                         {
                             // Index current cell
                             let cell_out_point = CellOutPoint {
                                 tx_hash: transaction.hash.clone(),
                                 index: Unsigned(i as u64),
                             };
-                            let eth_address = output.lock.args[0].as_bytes().into();
                             diff_cells
                                 .entry(eth_address)
                                 .and_modify(|e| {
@@ -238,6 +331,10 @@ impl Indexer {
                     &build_block_hash_key(next_block_number),
                     next_block.header.hash.clone(),
                 )?;
+                batch.put(
+                    &build_block_number_key(&next_block.header.hash),
+                    next_block_number.to_le_bytes(),
+                )?;
 
                 let mut all_spent_out_points = vec![];
                 let mut all_added_out_points = vec![];
@@ -283,11 +380,12 @@ impl Indexer {
                 )?;
 
                 self.db.write(batch)?;
+                self.notify(IndexerEvent::BlockIndexed(next_block_number));
             } else {
                 // No new block yet.
                 // TODO: purge old blocks
                 debug!("no new block available, sleeping ...");
-                sleep(Duration::from_secs(3));
+                sleep(self.poll_interval);
             }
         }
     }
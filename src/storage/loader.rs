@@ -1,28 +1,53 @@
 use super::{
-    build_receipt_key, load_latest_out_points, BlockNumber, Error, EthAccount, EthAddress,
-    EthBasicReceipt, EthCell, TransactionReceipt, BLOCK_KEY, CONTRACT_LOCK_CODE_DEP_KEY,
-    LOCK_CODE_DEP_KEY,
+    build_block_hash_key, build_block_number_key, build_block_receipt_hashes_key,
+    build_receipt_key, extract_lock_address, load_latest_out_points, logs_bloom, u256_to_u64,
+    Block, BlockNumber, BlockTransactions, Error, EthAccount, EthAddress, EthBasicReceipt, EthCell,
+    FeeHistory, Log, TransactionObject, TransactionReceipt, BASE_FEE_PER_GAS, BLOCK_GAS_LIMIT,
+    BLOCK_KEY, CONTRACT_LOCK_CODE_DEP_KEY, DEFAULT_PRIORITY_FEE_PER_GAS,
+    FINALIZED_BLOCK_CONFIRMATIONS, LOCK_CODE_DEP_KEY, SAFE_BLOCK_CONFIRMATIONS,
 };
 use crate::{CODE_HASH_CONTRACT_LOCK, CODE_HASH_LOCK};
 use bincode::deserialize;
+use byteorder::{ByteOrder, LittleEndian};
 use bytes::Bytes;
 use ckb_hash::blake2b_256;
-use ckb_jsonrpc_types::{CellOutPoint, OutPoint, TxStatus};
+use ckb_jsonrpc_types::{BlockView, CellOutPoint, JsonBytes, OutPoint, TransactionView, TxStatus};
 use ckb_sdk::HttpRpcClient;
 use numext_fixed_hash::H256;
+use numext_fixed_uint::U256;
 use rocksdb::DB;
-use std::sync::Arc;
+use std::cmp;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Maximum number of blocks eth_feeHistory could query at once
+const FEE_HISTORY_MAX_BLOCKS: u64 = 1024;
+// Number of recent blocks used to suggest priority fee
+const PRIORITY_FEE_SAMPLE_BLOCKS: u64 = 20;
+
+// Block fields exposed to the EVM
+#[derive(Clone)]
+pub struct BlockEnv {
+    pub author: EthAddress,
+    pub timestamp: u64,
+    pub difficulty: U256,
+}
 
 pub struct Loader {
     pub db: Arc<DB>,
+    pub chain_id: u64,
     ckb_uri: String,
+    // Environment of the last block transactions are run against, keyed by
+    // block hash so reverted blocks are never served from it.
+    block_env_cache: Mutex<Option<(H256, BlockEnv)>>,
 }
 
 impl Loader {
-    pub fn new(db: Arc<DB>, ckb_uri: &str) -> Result<Self, Error> {
+    pub fn new(db: Arc<DB>, ckb_uri: &str, chain_id: u64) -> Result<Self, Error> {
         let loader = Loader {
             db,
+            chain_id,
             ckb_uri: ckb_uri.to_string(),
+            block_env_cache: Mutex::new(None),
         };
         {
             let lock_out_point = loader.load_lock_out_point()?;
@@ -101,6 +126,149 @@ impl Loader {
     }
 
     pub fn load_receipt(&self, hash: &H256) -> Result<Option<TransactionReceipt>, Error> {
+        match self.load_committed_transaction(hash)? {
+            Some((basic_receipt, transaction, block_hash)) => Ok(Some(TransactionReceipt::from(
+                &basic_receipt,
+                &transaction,
+                &block_hash,
+                self.chain_id,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_transaction(&self, hash: &H256) -> Result<Option<TransactionObject>, Error> {
+        match self.load_committed_transaction(hash)? {
+            Some((basic_receipt, transaction, block_hash)) => Ok(Some(TransactionObject::from(
+                &basic_receipt,
+                &transaction,
+                &block_hash,
+                self.chain_id,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_transaction_by_index(
+        &self,
+        block_number: u64,
+        index: &U256,
+    ) -> Result<Option<TransactionObject>, Error> {
+        match self
+            .load_block_receipts(block_number)?
+            .into_iter()
+            .find(|(_, receipt)| &U256::from(receipt.transaction_index) == index)
+        {
+            Some((hash, _)) => self.load_transaction(&hash),
+            None => Ok(None),
+        }
+    }
+
+    // Resolves a CKB block hash to its block number, returns None if the
+    // block is not indexed or has been reverted.
+    pub fn load_block_number(&self, block_hash: &H256) -> Result<Option<u64>, Error> {
+        match self.db.get(&build_block_number_key(block_hash))? {
+            Some(data) => {
+                if data.len() != 8 {
+                    return Err(Error::MalformedData("Invalid block number!".to_string()));
+                }
+                Ok(Some(LittleEndian::read_u64(&data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_block(
+        &self,
+        block_number: u64,
+        full_transactions: bool,
+    ) -> Result<Option<Block>, Error> {
+        if block_number > self.tip_block_number()? {
+            return Ok(None);
+        }
+        let block_hash = match self.load_block_hash(block_number)? {
+            Some(block_hash) => block_hash,
+            None => return Ok(None),
+        };
+        let ckb_block = match self.ckb_client().get_block(block_hash.clone()).call()?.0 {
+            Some(ckb_block) => ckb_block,
+            None => return Ok(None),
+        };
+        let header = &ckb_block.header;
+        let receipts = self.load_block_receipts(block_number)?;
+        let gas_used = receipts
+            .iter()
+            .try_fold(U256::zero(), |sum, (_, receipt)| {
+                sum.checked_add(&receipt.gas_used)
+            })
+            .ok_or(Error::MalformedData("Gas addition overflow!".to_string()))?;
+        let transactions = if full_transactions {
+            let mut transactions = vec![];
+            for (hash, _) in &receipts {
+                if let Some(transaction) = self.load_transaction(hash)? {
+                    transactions.push(transaction);
+                }
+            }
+            BlockTransactions::Full(transactions)
+        } else {
+            BlockTransactions::Hashes(receipts.iter().map(|(hash, _)| hash.clone()).collect())
+        };
+        Ok(Some(Block {
+            number: block_number.into(),
+            hash: block_hash,
+            parent_hash: header.inner.parent_hash.clone(),
+            // CKB timestamps are in milliseconds
+            timestamp: (header.inner.timestamp.0 / 1000).into(),
+            gas_used,
+            gas_limit: BLOCK_GAS_LIMIT.into(),
+            logs_bloom: logs_bloom(receipts.iter().flat_map(|(_, receipt)| &receipt.logs)),
+            base_fee_per_gas: BASE_FEE_PER_GAS.into(),
+            miner: JsonBytes::from_bytes(block_author(&ckb_block, self.chain_id).0),
+            extra_data: JsonBytes::from_bytes(Bytes::new()),
+            difficulty: header.inner.difficulty.clone(),
+            transactions,
+        }))
+    }
+
+    // Loads fields of an indexed block needed to run transactions on top of
+    // it. Unlike load_block, no receipts are read, and the result is cached
+    // since transactions are mostly run against the tip.
+    pub fn load_block_env(&self, block_number: u64) -> Result<Option<BlockEnv>, Error> {
+        let block_hash = match self.load_block_hash(block_number)? {
+            Some(block_hash) => block_hash,
+            None => return Ok(None),
+        };
+        if let Some((cached_hash, block_env)) = &*self.lock_block_env_cache()? {
+            if cached_hash == &block_hash {
+                return Ok(Some(block_env.clone()));
+            }
+        }
+        let ckb_block = match self.ckb_client().get_block(block_hash.clone()).call()?.0 {
+            Some(ckb_block) => ckb_block,
+            None => return Ok(None),
+        };
+        let block_env = BlockEnv {
+            author: block_author(&ckb_block, self.chain_id),
+            // CKB timestamps are in milliseconds
+            timestamp: ckb_block.header.inner.timestamp.0 / 1000,
+            difficulty: ckb_block.header.inner.difficulty.clone(),
+        };
+        *self.lock_block_env_cache()? = Some((block_hash, block_env.clone()));
+        Ok(Some(block_env))
+    }
+
+    fn lock_block_env_cache(&self) -> Result<MutexGuard<Option<(H256, BlockEnv)>>, Error> {
+        self.block_env_cache
+            .lock()
+            .map_err(|_| Error::MalformedData("Block env cache is poisoned!".to_string()))
+    }
+
+    // Loads basic receipt of an Ethereum transaction, together with the
+    // committed CKB transaction containing it and CKB block hash.
+    fn load_committed_transaction(
+        &self,
+        hash: &H256,
+    ) -> Result<Option<(EthBasicReceipt, TransactionView, H256)>, Error> {
         let basic_receipt: EthBasicReceipt = match self.db.get(&build_receipt_key(hash))? {
             Some(data) => deserialize(&data)?,
             None => return Ok(None),
@@ -119,18 +287,162 @@ impl Loader {
         if transaction.tx_status.block_hash.is_none() {
             return Ok(None);
         }
-        Ok(Some(TransactionReceipt::from(
-            &basic_receipt,
-            &transaction.transaction,
-            &transaction.tx_status.block_hash.unwrap(),
-        )?))
+        Ok(Some((
+            basic_receipt,
+            transaction.transaction,
+            transaction.tx_status.block_hash.unwrap(),
+        )))
+    }
+
+    pub fn load_block_hash(&self, block_number: u64) -> Result<Option<H256>, Error> {
+        match self.db.get(&build_block_hash_key(block_number))? {
+            Some(data) => Ok(Some(H256::from_slice(&data).map_err(|e| {
+                Error::MalformedData(format!("Invalid block hash: {:?}", e).to_string())
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    // Returns basic receipts in current block ordered by transaction index,
+    // together with Ethereum transaction hashes.
+    pub fn load_block_receipts(
+        &self,
+        block_number: u64,
+    ) -> Result<Vec<(H256, EthBasicReceipt)>, Error> {
+        let receipt_hashes: Vec<H256> =
+            match self.db.get(&build_block_receipt_hashes_key(block_number))? {
+                Some(data) => deserialize(&data)?,
+                None => return Ok(vec![]),
+            };
+        let mut receipts = receipt_hashes
+            .into_iter()
+            .map(|hash| {
+                let data = self
+                    .db
+                    .get(&build_receipt_key(&hash))?
+                    .ok_or(Error::MalformedData(
+                        "Receipt key does not exist!".to_string(),
+                    ))?;
+                Ok((hash, deserialize(&data)?))
+            })
+            .collect::<Result<Vec<(H256, EthBasicReceipt)>, Error>>()?;
+        receipts.sort_by_key(|(_, receipt)| receipt.transaction_index);
+        Ok(receipts)
+    }
+
+    pub fn load_logs(&self, block_number: u64) -> Result<Vec<Log>, Error> {
+        let block_hash = match self.load_block_hash(block_number)? {
+            Some(block_hash) => block_hash,
+            None => return Ok(vec![]),
+        };
+        Ok(self
+            .load_block_receipts(block_number)?
+            .iter()
+            .flat_map(|(hash, receipt)| Log::from_receipt(receipt, hash, &block_hash))
+            .collect())
+    }
+
+    pub fn load_fee_history(
+        &self,
+        block_count: u64,
+        newest_block: u64,
+        reward_percentiles: &Option<Vec<f64>>,
+    ) -> Result<FeeHistory, Error> {
+        if let Some(percentiles) = reward_percentiles {
+            let mut last = 0.0;
+            for percentile in percentiles {
+                if *percentile < last || *percentile > 100.0 {
+                    return Err(Error::MalformedData(format!(
+                        "Invalid reward percentile: {}",
+                        percentile
+                    )));
+                }
+                last = *percentile;
+            }
+        }
+        let block_count = cmp::min(block_count, FEE_HISTORY_MAX_BLOCKS);
+        let newest_block = cmp::min(newest_block, self.tip_block_number()?);
+        // Block 0 is CKB genesis block, which is never indexed
+        let oldest_block = cmp::max(newest_block + 1 - cmp::min(block_count, newest_block), 1);
+        let mut base_fee_per_gas: Vec<U256> = vec![];
+        let mut gas_used_ratio = vec![];
+        let mut reward = vec![];
+        for block_number in oldest_block..=newest_block {
+            let receipts = self.load_block_receipts(block_number)?;
+            let gas_used = receipts.iter().try_fold(0u64, |sum, (_, receipt)| {
+                sum.checked_add(u256_to_u64(&receipt.gas_used)?)
+                    .ok_or(Error::MalformedData("Gas addition overflow!".to_string()))
+            })?;
+            base_fee_per_gas.push(BASE_FEE_PER_GAS.into());
+            gas_used_ratio.push(gas_used as f64 / BLOCK_GAS_LIMIT as f64);
+            if let Some(percentiles) = reward_percentiles {
+                reward.push(calculate_rewards(&receipts, percentiles)?);
+            }
+        }
+        base_fee_per_gas.push(BASE_FEE_PER_GAS.into());
+        Ok(FeeHistory {
+            oldest_block: oldest_block.into(),
+            base_fee_per_gas,
+            gas_used_ratio,
+            reward: reward_percentiles.as_ref().map(|_| reward),
+        })
+    }
+
+    // Median priority fee paid by transactions in recent blocks
+    pub fn suggest_priority_fee(&self) -> Result<U256, Error> {
+        let tip_block = self.tip_block_number()?;
+        let mut fees = vec![];
+        for block_number in tip_block.saturating_sub(PRIORITY_FEE_SAMPLE_BLOCKS - 1)..=tip_block {
+            for (_, receipt) in self.load_block_receipts(block_number)? {
+                fees.push(priority_fee(&receipt));
+            }
+        }
+        if fees.is_empty() {
+            return Ok(DEFAULT_PRIORITY_FEE_PER_GAS.into());
+        }
+        fees.sort();
+        Ok(fees[fees.len() / 2].clone())
     }
 
     pub fn resolve_block_number(&self, block_number: BlockNumber) -> Result<u64, Error> {
+        let tip = self.tip_block_number()?;
         match block_number {
-            BlockNumber::Latest => self.tip_block_number(),
-            BlockNumber::Number(n) => Ok(n),
+            // Pending state is built on top of the indexed tip
+            BlockNumber::Latest | BlockNumber::Pending => Ok(tip),
+            BlockNumber::Earliest => Ok(0),
+            BlockNumber::Safe => Ok(tip.saturating_sub(SAFE_BLOCK_CONFIRMATIONS)),
+            BlockNumber::Finalized => Ok(tip.saturating_sub(FINALIZED_BLOCK_CONFIRMATIONS)),
+            BlockNumber::Number(n) => {
+                if n > tip {
+                    return Err(Error::MalformedData(format!(
+                        "Block {} is ahead of indexed tip {}!",
+                        n, tip
+                    )));
+                }
+                Ok(n)
+            }
+            // Blocks reverted by the indexer have their hashes removed, so
+            // any block found here is canonical.
+            BlockNumber::Hash(block_hash, _require_canonical) => {
+                self.load_block_number(&block_hash)?.ok_or_else(|| {
+                    Error::MalformedData(format!("Block {:x} is not found!", block_hash))
+                })
+            }
+        }
+    }
+
+    // Returns None for block numbers ahead of the indexed tip, which are not
+    // errors for queries like eth_getBlockByNumber.
+    pub fn try_resolve_block_number(
+        &self,
+        block_number: BlockNumber,
+    ) -> Result<Option<u64>, Error> {
+        if let BlockNumber::Number(n) = block_number {
+            if n > self.tip_block_number()? {
+                return Ok(None);
+            }
         }
+        self.resolve_block_number(block_number).map(Some)
     }
 
     pub fn tip_block_number(&self) -> Result<u64, Error> {
@@ -183,3 +495,51 @@ impl Loader {
         })
     }
 }
+
+fn priority_fee(receipt: &EthBasicReceipt) -> U256 {
+    receipt
+        .effective_gas_price
+        .checked_sub(&U256::from(BASE_FEE_PER_GAS))
+        .unwrap_or_else(U256::zero)
+}
+
+// Priority fees at each percentile of gas used in a block, transactions are
+// sorted by priority fee and weighted by gas used.
+fn calculate_rewards(
+    receipts: &[(H256, EthBasicReceipt)],
+    percentiles: &[f64],
+) -> Result<Vec<U256>, Error> {
+    let mut fees = receipts
+        .iter()
+        .map(|(_, receipt)| Ok((priority_fee(receipt), u256_to_u64(&receipt.gas_used)?)))
+        .collect::<Result<Vec<(U256, u64)>, Error>>()?;
+    if fees.is_empty() {
+        return Ok(percentiles.iter().map(|_| U256::zero()).collect());
+    }
+    fees.sort_by(|a, b| a.0.cmp(&b.0));
+    let total_gas: u64 = fees.iter().map(|(_, gas)| gas).sum();
+    let mut rewards = vec![];
+    let mut index = 0;
+    let mut sum_gas = fees[0].1;
+    for percentile in percentiles {
+        let threshold = (total_gas as f64 * percentile / 100.0) as u64;
+        while sum_gas < threshold && index < fees.len() - 1 {
+            index += 1;
+            sum_gas += fees[index].1;
+        }
+        rewards.push(fees[index].0.clone());
+    }
+    Ok(rewards)
+}
+
+// Block assembler of a CKB block, which is the Ethereum address in cellbase
+// lock when it's a polyjuice lock, or zero address otherwise.
+fn block_author(block: &BlockView, chain_id: u64) -> EthAddress {
+    block
+        .transactions
+        .get(0)
+        .and_then(|cellbase| cellbase.inner.outputs.get(0))
+        .filter(|output| output.lock.code_hash.as_bytes() == CODE_HASH_LOCK)
+        .and_then(|output| extract_lock_address(&output.lock, chain_id))
+        .unwrap_or_default()
+}
@@ -1,34 +1,64 @@
 mod indexer;
 mod loader;
+mod precompile;
 mod runner;
+mod txpool;
 
-use crate::{Error, CODE_HASH_CONTRACT_LOCK, SECP256K1};
+use crate::{Error, CODE_HASH_CONTRACT_LOCK, CODE_HASH_LOCK, SECP256K1};
 use bincode::{deserialize, serialize};
 use bytes::{BufMut, Bytes, BytesMut};
 use ckb_core::transaction::Witness;
-use ckb_jsonrpc_types::{Capacity, CellOutPoint, CellOutput, JsonBytes, TransactionView};
+use ckb_jsonrpc_types::{
+    Capacity, CellOutPoint, CellOutput, JsonBytes, Script, TransactionView, Witness as JsonWitness,
+};
 use ckb_occupied_capacity::AsCapacity;
-use ethereum_types::Address as ParityAddress;
-use faster_hex::hex_decode;
+use ethereum_types::{Address as ParityAddress, Bloom, BloomInput};
+use faster_hex::{hex_decode, hex_string};
 use numext_fixed_hash::H256;
 use numext_fixed_uint::{u256, U256};
-use rlp::{encode_list, Rlp};
-use rocksdb::DB;
+use rlp::{encode_list, Rlp, RlpStream};
+use rocksdb::{WriteBatch, DB};
 use secp256k1::{Message, RecoverableSignature, RecoveryId};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::sync::Arc;
 use tiny_keccak::keccak256;
 
-pub use indexer::Indexer;
-pub use loader::Loader;
-pub use runner::Runner;
+pub use indexer::{Indexer, IndexerEvent};
+pub use loader::{BlockEnv, Loader};
+pub use runner::{decode_revert_reason, Runner};
+pub use txpool::{PendingCells, PendingTransaction, RejectedTransaction, TxPool};
 
-pub const CHAIN_ID: u64 = 1;
+pub const DEFAULT_CHAIN_ID: u64 = 1;
 pub const BLOCK_KEY: &str = "block";
 pub const LOCK_CODE_DEP_KEY: &str = "lock_dep";
 pub const CONTRACT_LOCK_CODE_DEP_KEY: &str = "contract_lock_dep";
+pub const RESULT_WITNESS_FLAG: &[u8] = b"result";
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+// Bump this whenever the layout of indexed data changes, indexed data
+// written by other versions is dropped and rebuilt from CKB on startup.
+// Version 2: transaction index of receipts starts at 0
+// Version 3: chain ID is part of lock script args of indexed cells
+pub const SCHEMA_VERSION: u32 = 3;
+// CKB blocks are packed by CKB miners, so total gas of a block could not be
+// limited. This caps the gas limit of each transaction instead, and is
+// reported as block gas limit for Ethereum tooling.
+pub const BLOCK_GAS_LIMIT: u64 = 12_500_000;
+// Polyjuice has no fee market, base fee is fixed and the whole effective gas
+// price goes to CKB miners as transaction fee.
+pub const BASE_FEE_PER_GAS: u64 = 0;
+// Suggested priority fee when there are no recent transactions, 1 gwei.
+pub const DEFAULT_PRIORITY_FEE_PER_GAS: u64 = 1_000_000_000;
+// CKB has no finality gadget, "safe" and "finalized" blocks are approximated
+// by the number of confirmations on top of the indexed tip.
+pub const SAFE_BLOCK_CONFIRMATIONS: u64 = 6;
+pub const FINALIZED_BLOCK_CONFIRMATIONS: u64 = 24;
+
+pub const LEGACY_TRANSACTION_TYPE: u8 = 0x00;
+pub const ACCESS_LIST_TRANSACTION_TYPE: u8 = 0x01;
+pub const DYNAMIC_FEE_TRANSACTION_TYPE: u8 = 0x02;
 
 pub const SHANNON_TO_WEI: U256 = u256!("10_000_000_000");
 
@@ -77,6 +107,12 @@ pub fn build_block_hash_key(block_number: u64) -> Bytes {
     key.freeze()
 }
 
+pub fn build_block_number_key(block_hash: &H256) -> Bytes {
+    let mut key = BytesMut::from("h:");
+    key.extend_from_slice(block_hash.as_bytes());
+    key.freeze()
+}
+
 pub fn build_receipt_key(tx_hash: &H256) -> Bytes {
     let mut key = BytesMut::from("r:");
     key.extend_from_slice(tx_hash.as_bytes());
@@ -130,6 +166,33 @@ pub fn load_latest_out_points(
     Ok(vec![])
 }
 
+// Deletes everything except registered lock cells when indexed data was
+// written with a different schema, so the indexer starts over from the
+// genesis block.
+pub fn check_schema_version(db: &DB) -> Result<(), Error> {
+    let version: Option<u32> = match db.get(SCHEMA_VERSION_KEY)? {
+        Some(data) => Some(deserialize(&data)?),
+        None => None,
+    };
+    if version == Some(SCHEMA_VERSION) {
+        return Ok(());
+    }
+    let mut batch = WriteBatch::default();
+    let mut iter = db.raw_iterator();
+    iter.seek_to_first();
+    while iter.valid() {
+        if let Some(key) = iter.key() {
+            if key != LOCK_CODE_DEP_KEY.as_bytes() && key != CONTRACT_LOCK_CODE_DEP_KEY.as_bytes() {
+                batch.delete(&key)?;
+            }
+        }
+        iter.next();
+    }
+    batch.put(SCHEMA_VERSION_KEY, &serialize(&SCHEMA_VERSION)?)?;
+    db.write(batch)?;
+    Ok(())
+}
+
 // TODO: some of the following functions are better implemented as serde
 // trait implementations. But serde takes some trouble to get right, so we
 // are sticking with simple solution now and make the change later.
@@ -137,6 +200,35 @@ pub fn load_latest_out_points(
 #[derive(Debug, Clone)]
 pub struct EthCell(pub CellOutput, pub CellOutPoint);
 
+// Lock script of polyjuice cells, code_hash is either CODE_HASH_LOCK or
+// CODE_HASH_CONTRACT_LOCK. Chain ID is part of the script args, so one build
+// of the lock scripts serves every chain ID while accounts of different
+// chains never share cells.
+pub fn build_lock_script(code_hash: [u8; 32], eth_address: &EthAddress, chain_id: u64) -> Script {
+    let mut lock = Script::default();
+    lock.code_hash = code_hash.into();
+    lock.args.push(JsonBytes::from_bytes(eth_address.0.clone()));
+    lock.args.push(JsonBytes::from_bytes(Bytes::from(
+        &chain_id.to_le_bytes()[..],
+    )));
+    lock
+}
+
+// Ethereum address owning a cell, or None if the cell is not locked by
+// polyjuice lock scripts of the given chain.
+pub fn extract_lock_address(lock: &Script, chain_id: u64) -> Option<EthAddress> {
+    let code_hash = lock.code_hash.as_bytes();
+    if (code_hash == CODE_HASH_LOCK || code_hash == CODE_HASH_CONTRACT_LOCK)
+        && lock.args.len() == 2
+        && lock.args[0].len() == 20
+        && lock.args[1].as_bytes() == chain_id.to_le_bytes()
+    {
+        Some(lock.args[0].as_bytes().into())
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EthAccount {
     pub main_cell: Option<EthCell>,
@@ -156,7 +248,7 @@ impl EthAccount {
 
     pub fn contract_data(&self) -> Result<EthContractData, Error> {
         if let Some(main_cell) = &self.main_cell {
-            Ok(deserialize(&main_cell.0.data.as_bytes()[1..])?)
+            EthContractData::from_slice(&main_cell.0.data.as_bytes()[1..])
         } else {
             Err(Error::MalformedData(
                 "Contract must have main cell!".to_string(),
@@ -181,12 +273,18 @@ impl EthAccount {
         }
     }
 
+    // Fund cells sent to a contract address use normal lock, which requires
+    // a signature nobody could provide, so only the main cell counts towards
+    // contract balance.
     pub fn total_capacities(&self) -> Result<Capacity, Error> {
         let main_capacity = self
             .main_cell
             .clone()
             .map(|cell| cell.0.capacity)
             .unwrap_or(Capacity(0u64.as_capacity()));
+        if self.contract_account()? {
+            return Ok(main_capacity);
+        }
         self.fund_cells
             .iter()
             .try_fold(main_capacity, |sum, cell| {
@@ -196,19 +294,47 @@ impl EthAccount {
     }
 
     pub fn total_capacities_in_wei(&self) -> Result<U256, Error> {
-        let capacities: U256 = self.total_capacities()?.0.as_u64().into();
-        let wei = capacities
-            .checked_mul(&SHANNON_TO_WEI)
-            .ok_or(Error::MalformedData(
-                "Shannon cannot be expressed in wei!".to_string(),
-            ))?;
-        Ok(wei)
+        capacity_to_wei(&self.total_capacities()?)
     }
 }
 
+pub fn capacity_to_wei(capacity: &Capacity) -> Result<U256, Error> {
+    let capacities: U256 = capacity.0.as_u64().into();
+    let wei = capacities
+        .checked_mul(&SHANNON_TO_WEI)
+        .ok_or(Error::MalformedData(
+            "Shannon cannot be expressed in wei!".to_string(),
+        ))?;
+    Ok(wei)
+}
+
 pub enum BlockNumber {
     Latest,
+    Earliest,
+    Pending,
+    Safe,
+    Finalized,
     Number(u64),
+    // Block hash and whether the block must be canonical
+    Hash(H256, bool),
+}
+
+// Block parameter of state querying RPCs, EIP-1898 allows an object besides
+// block numbers and tags.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum BlockParameter {
+    Tag(String),
+    Hash {
+        #[serde(rename = "blockHash")]
+        block_hash: H256,
+        #[serde(rename = "requireCanonical", default)]
+        require_canonical: bool,
+    },
+    Number {
+        #[serde(rename = "blockNumber")]
+        block_number: String,
+    },
 }
 
 impl BlockNumber {
@@ -219,13 +345,32 @@ impl BlockNumber {
         }
     }
 
+    pub fn from_parameter(p: &Option<BlockParameter>) -> Result<BlockNumber, Error> {
+        match p {
+            Some(BlockParameter::Tag(s)) => BlockNumber::parse(&s),
+            Some(BlockParameter::Hash {
+                block_hash,
+                require_canonical,
+            }) => Ok(BlockNumber::Hash(block_hash.clone(), *require_canonical)),
+            Some(BlockParameter::Number { block_number }) => BlockNumber::parse(&block_number),
+            None => Ok(BlockNumber::Latest),
+        }
+    }
+
     pub fn parse(s: &str) -> Result<BlockNumber, Error> {
         match s {
             "latest" => Ok(BlockNumber::Latest),
-            n if n.starts_with("0x") && (!n.starts_with("0x0")) => Ok(BlockNumber::Number(
-                u64::from_str_radix(&s[2..], 16)
-                    .map_err(|e| Error::MalformedData(e.to_string()))?,
-            )),
+            "earliest" => Ok(BlockNumber::Earliest),
+            "pending" => Ok(BlockNumber::Pending),
+            "safe" => Ok(BlockNumber::Safe),
+            "finalized" => Ok(BlockNumber::Finalized),
+            "0x0" => Ok(BlockNumber::Number(0)),
+            n if n.len() > 2 && n.starts_with("0x") && (!n.starts_with("0x0")) => {
+                Ok(BlockNumber::Number(
+                    u64::from_str_radix(&s[2..], 16)
+                        .map_err(|e| Error::MalformedData(e.to_string()))?,
+                ))
+            }
             _ => Err(Error::MalformedData(
                 format!("Invalid block number: {}", s).to_string(),
             )),
@@ -233,7 +378,7 @@ impl BlockNumber {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct EthAddress(pub Bytes);
 
 impl Default for EthAddress {
@@ -254,6 +399,10 @@ impl EthAddress {
             .map_err(|e| Error::MalformedData(e.to_string()))?;
         Ok(EthAddress(Bytes::from(&b[..])))
     }
+
+    pub fn to_hex(&self) -> String {
+        format!("0x{}", hex_string(&self.0).expect("hex encode"))
+    }
 }
 
 impl<'a> From<&'a [u8]> for EthAddress {
@@ -287,14 +436,45 @@ pub fn serialize_u64(n: u64) -> String {
     format!("0x{:x}", n).to_string()
 }
 
-#[derive(Debug)]
+pub fn parse_u64(s: &str) -> Result<u64, Error> {
+    if !s.starts_with("0x") {
+        return Err(Error::MalformedData(format!("Invalid quantity: {}", s)));
+    }
+    u64::from_str_radix(&s[2..], 16).map_err(|e| Error::MalformedData(e.to_string()))
+}
+
+pub fn u256_to_u64(v: &U256) -> Result<u64, Error> {
+    let bytes = v.to_le_bytes();
+    if bytes[8..].iter().any(|b| *b != 0) {
+        return Err(Error::MalformedData("Value exceeds u64 range!".to_string()));
+    }
+    let mut u64_bytes = [0u8; 8];
+    u64_bytes.copy_from_slice(&bytes[0..8]);
+    Ok(u64::from_le_bytes(u64_bytes))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccessListItem {
+    pub address: JsonBytes,
+    #[serde(rename = "storageKeys")]
+    pub storage_keys: Vec<H256>,
+}
+
+#[derive(Debug, Clone)]
 pub struct EthTransaction {
+    // EIP-2718 transaction type, 0 for legacy transactions
+    pub transaction_type: u8,
     pub nonce: u64,
+    // For EIP-1559 transactions, this is max fee per gas
     pub gas_price: U256,
+    // Same as gas price for transactions before EIP-1559
+    pub max_priority_fee_per_gas: U256,
     pub gas_limit: U256,
     pub to: Option<EthAddress>,
     pub value: U256,
     pub data: Option<Bytes>,
+    pub access_list: Vec<AccessListItem>,
+    // For typed transactions, this is y parity of the signature
     pub v: u64,
     pub r: U256,
     pub s: U256,
@@ -308,6 +488,7 @@ impl EthTransaction {
         wei_to_capacity(&self.value)
     }
 
+    // Maximum fees the sender might pay
     pub fn fees(&self) -> Result<U256, Error> {
         self.gas_price
             .checked_mul(&self.gas_limit)
@@ -316,15 +497,49 @@ impl EthTransaction {
             ))
     }
 
+    // Fees actually paid for the specified amount of gas
+    pub fn fees_with_gas(&self, gas: &U256) -> Result<U256, Error> {
+        self.effective_gas_price()
+            .checked_mul(gas)
+            .ok_or(Error::MalformedData(
+                "Wei multiplication overflow!".to_string(),
+            ))
+    }
+
+    pub fn effective_gas_price(&self) -> U256 {
+        let price = U256::from(BASE_FEE_PER_GAS)
+            .checked_add(&self.max_priority_fee_per_gas)
+            .unwrap_or_else(U256::max_value);
+        cmp::min(price, self.gas_price.clone())
+    }
+
     pub fn fees_in_capacity(&self) -> Result<Capacity, Error> {
         wei_to_capacity(&self.fees()?)
     }
 
+    // Transactions signed without EIP-155 replay protection
+    pub fn is_unprotected(&self) -> bool {
+        self.transaction_type == LEGACY_TRANSACTION_TYPE && is_unprotected_v(self.v)
+    }
+
     pub fn hash(&self) -> H256 {
         keccak256(&self.raw).into()
     }
 
-    pub fn from_raw(raw: Bytes) -> Result<EthTransaction, Error> {
+    pub fn from_raw(raw: Bytes, chain_id: u64) -> Result<EthTransaction, Error> {
+        match raw.first() {
+            Some(&ACCESS_LIST_TRANSACTION_TYPE) | Some(&DYNAMIC_FEE_TRANSACTION_TYPE) => {
+                EthTransaction::from_typed_raw(raw, chain_id)
+            }
+            // Legacy transactions are RLP lists
+            Some(b) if *b >= 0xc0 => EthTransaction::from_legacy_raw(raw, chain_id),
+            _ => Err(Error::MalformedData(
+                "Unsupported transaction type!".to_string(),
+            )),
+        }
+    }
+
+    fn from_legacy_raw(raw: Bytes, chain_id: u64) -> Result<EthTransaction, Error> {
         let bytes: Vec<Vec<u8>> = Rlp::new(&raw).as_list()?;
         if bytes.len() != 9 {
             return Err(Error::MalformedData(
@@ -341,9 +556,12 @@ impl EthTransaction {
                 format!("Invalid s length: {}", bytes[8].len()).to_string(),
             ));
         }
+        let gas_price = bytes_to_u256(&bytes[1])?;
         let tx = EthTransaction {
+            transaction_type: LEGACY_TRANSACTION_TYPE,
             nonce: bytes_to_u64(&bytes[0])?,
-            gas_price: bytes_to_u256(&bytes[1])?,
+            max_priority_fee_per_gas: gas_price.clone(),
+            gas_price,
             gas_limit: bytes_to_u256(&bytes[2])?,
             to: if bytes[3].len() > 0 {
                 Some(EthAddress(Bytes::from(&bytes[3][..])))
@@ -356,14 +574,127 @@ impl EthTransaction {
             } else {
                 None
             },
+            access_list: vec![],
             v: bytes_to_u64(&bytes[6])?,
             r: bytes_to_u256(&bytes[7])?,
             s: bytes_to_u256(&bytes[8])?,
-            from: extract_from_address(&bytes)?,
+            from: extract_from_address(&bytes, chain_id)?,
             raw,
         };
         Ok(tx)
     }
+
+    // EIP-2718 envelope: transaction type followed by RLP payload. EIP-2930
+    // payload is [chainId, nonce, gasPrice, gasLimit, to, value, data,
+    // accessList, yParity, r, s], EIP-1559 replaces gasPrice with
+    // maxPriorityFeePerGas and maxFeePerGas.
+    fn from_typed_raw(raw: Bytes, chain_id: u64) -> Result<EthTransaction, Error> {
+        let transaction_type = raw[0];
+        let rlp = Rlp::new(&raw[1..]);
+        let fee_fields = if transaction_type == DYNAMIC_FEE_TRANSACTION_TYPE {
+            2
+        } else {
+            1
+        };
+        let item_count = rlp.item_count()?;
+        if item_count != 9 + fee_fields {
+            return Err(Error::MalformedData(
+                format!("Invalid data length: {}", item_count).to_string(),
+            ));
+        }
+        let field = |i: usize| -> Result<Vec<u8>, Error> { Ok(rlp.at(i)?.data()?.to_vec()) };
+        let transaction_chain_id = bytes_to_u64(&field(0)?)?;
+        if transaction_chain_id != chain_id {
+            return Err(Error::MalformedData(format!(
+                "Invalid chain ID: {}",
+                transaction_chain_id
+            )));
+        }
+        let (max_priority_fee_per_gas, gas_price) = if fee_fields == 2 {
+            (bytes_to_u256(&field(2)?)?, bytes_to_u256(&field(3)?)?)
+        } else {
+            let gas_price = bytes_to_u256(&field(2)?)?;
+            (gas_price.clone(), gas_price)
+        };
+        // Index of gas limit, following fields are at fixed offsets from it
+        let i = 2 + fee_fields;
+        let to = field(i + 1)?;
+        let data = field(i + 3)?;
+        let (y_parity, r, s) = (field(i + 5)?, field(i + 6)?, field(i + 7)?);
+        if r.len() != 32 {
+            return Err(Error::MalformedData(
+                format!("Invalid r length: {}", r.len()).to_string(),
+            ));
+        }
+        if s.len() != 32 {
+            return Err(Error::MalformedData(
+                format!("Invalid s length: {}", s.len()).to_string(),
+            ));
+        }
+        let y_parity = bytes_to_u64(&y_parity)?;
+        if y_parity > 1 {
+            return Err(Error::MalformedData(
+                format!("Invalid recovery: {}", y_parity).to_string(),
+            ));
+        }
+        // Signing payload: type || rlp(all fields except signature)
+        let mut stream = RlpStream::new_list(i + 5);
+        for j in 0..(i + 5) {
+            stream.append_raw(rlp.at(j)?.as_raw(), 1);
+        }
+        let mut message = vec![transaction_type];
+        message.extend_from_slice(&stream.out());
+        let from = recover_address(&keccak256(&message), y_parity as u8, &r, &s)?;
+        Ok(EthTransaction {
+            transaction_type,
+            nonce: bytes_to_u64(&field(1)?)?,
+            gas_price,
+            max_priority_fee_per_gas,
+            gas_limit: bytes_to_u256(&field(i)?)?,
+            to: if to.len() > 0 {
+                Some(EthAddress(Bytes::from(&to[..])))
+            } else {
+                None
+            },
+            value: bytes_to_u256(&field(i + 2)?)?,
+            data: if data.len() > 0 {
+                Some(Bytes::from(&data[..]))
+            } else {
+                None
+            },
+            access_list: parse_access_list(&rlp.at(i + 4)?)?,
+            v: y_parity,
+            r: bytes_to_u256(&r)?,
+            s: bytes_to_u256(&s)?,
+            from,
+            raw,
+        })
+    }
+}
+
+fn parse_access_list(rlp: &Rlp) -> Result<Vec<AccessListItem>, Error> {
+    rlp.iter()
+        .map(|item| {
+            let address = item.at(0)?.data()?;
+            if address.len() != 20 {
+                return Err(Error::MalformedData(
+                    "Invalid access list address!".to_string(),
+                ));
+            }
+            let storage_keys = item
+                .at(1)?
+                .iter()
+                .map(|key| {
+                    H256::from_slice(key.data()?)
+                        .map_err(|e| Error::MalformedData(format!("Invalid storage key: {:?}", e)))
+                })
+                .collect::<Result<Vec<H256>, Error>>()?;
+            Ok(AccessListItem {
+                address: JsonBytes::from_bytes(Bytes::from(address)),
+                storage_keys,
+            })
+        })
+        .collect()
 }
 
 fn wei_to_capacity(w: &U256) -> Result<Capacity, Error> {
@@ -380,21 +711,35 @@ fn wei_to_capacity(w: &U256) -> Result<Capacity, Error> {
     Ok(Capacity(u64::from_le_bytes(capacity_bytes).as_capacity()))
 }
 
-fn extract_from_address(bytes: &[Vec<u8>]) -> Result<EthAddress, Error> {
-    let recovery = calculate_sig_recovery(bytes_to_u64(&bytes[6])?)?;
-    let recovery_id = RecoveryId::from_i32(recovery as i32)?;
+fn extract_from_address(bytes: &[Vec<u8>], chain_id: u64) -> Result<EthAddress, Error> {
+    let recovery = calculate_sig_recovery(bytes_to_u64(&bytes[6])?, chain_id)?;
     let mut unsigned_tx = bytes.to_vec();
-    // TODO: fix this later
-    assert!(CHAIN_ID <= 0xFF);
-    unsigned_tx[6] = vec![CHAIN_ID as u8];
-    unsigned_tx[7] = vec![];
-    unsigned_tx[8] = vec![];
+    if is_unprotected_v(bytes_to_u64(&bytes[6])?) {
+        // Pre EIP-155 transactions only sign the first 6 fields
+        unsigned_tx.truncate(6);
+    } else {
+        // EIP-155: chain ID is signed as a big endian integer without
+        // leading zeros.
+        unsigned_tx[6] = chain_id
+            .to_be_bytes()
+            .iter()
+            .skip_while(|b| **b == 0)
+            .cloned()
+            .collect();
+        unsigned_tx[7] = vec![];
+        unsigned_tx[8] = vec![];
+    }
     let serialized_unsigned_tx = encode_list::<Vec<u8>, _>(&unsigned_tx);
     let serialized_unsigned_tx_hash = keccak256(&serialized_unsigned_tx).to_vec();
-    let message = Message::from_slice(&serialized_unsigned_tx_hash[..])?;
+    recover_address(&serialized_unsigned_tx_hash, recovery, &bytes[7], &bytes[8])
+}
+
+fn recover_address(message: &[u8], recovery: u8, r: &[u8], s: &[u8]) -> Result<EthAddress, Error> {
+    let recovery_id = RecoveryId::from_i32(recovery as i32)?;
+    let message = Message::from_slice(message)?;
     let mut signature_data = [0u8; 64];
-    signature_data[..32].copy_from_slice(&bytes[7]);
-    signature_data[32..].copy_from_slice(&bytes[8]);
+    signature_data[..32].copy_from_slice(r);
+    signature_data[32..].copy_from_slice(s);
     let signature = RecoverableSignature::from_compact(&signature_data, recovery_id)?;
     let public_key = SECP256K1.recover(&message, &signature)?;
     let serialized_public_key = public_key.serialize_uncompressed();
@@ -427,8 +772,19 @@ fn bytes_to_u256(bytes: &[u8]) -> Result<U256, Error> {
     Ok(U256::from_be_bytes(&data))
 }
 
-fn calculate_sig_recovery(v: u64) -> Result<u8, Error> {
-    let v = v - (2 * CHAIN_ID + 35);
+fn is_unprotected_v(v: u64) -> bool {
+    v == 27 || v == 28
+}
+
+fn calculate_sig_recovery(v: u64, chain_id: u64) -> Result<u8, Error> {
+    if is_unprotected_v(v) {
+        return Ok((v - 27) as u8);
+    }
+    let v = chain_id
+        .checked_mul(2)
+        .and_then(|c| c.checked_add(35))
+        .and_then(|c| v.checked_sub(c))
+        .ok_or(Error::MalformedData(format!("Invalid v: {}", v)))?;
     if v != 0 && v != 1 {
         return Err(Error::MalformedData(
             format!("Invalid recovery: {}", v).to_string(),
@@ -437,14 +793,103 @@ fn calculate_sig_recovery(v: u64) -> Result<u8, Error> {
     Ok(v as u8)
 }
 
+// Results of running an Ethereum transaction are kept in an extra witness of
+// the generated CKB transaction, tagged with RESULT_WITNESS_FLAG, so the
+// indexer can recover them without re-running the EVM.
+pub fn extract_execution_result(
+    witnesses: &[JsonWitness],
+) -> Result<Option<EthExecutionResult>, Error> {
+    for witness in witnesses {
+        let witness: Witness = witness.clone().into();
+        if witness.len() == 2 && witness[0].as_ref() == RESULT_WITNESS_FLAG {
+            return Ok(Some(deserialize(&witness[1])?));
+        }
+    }
+    Ok(None)
+}
+
+pub fn logs_bloom<'a, I: IntoIterator<Item = &'a EthLog>>(logs: I) -> JsonBytes {
+    let mut bloom = Bloom::default();
+    for log in logs {
+        bloom.accrue(BloomInput::Raw(log.address.as_ref()));
+        for topic in &log.topics {
+            bloom.accrue(BloomInput::Raw(topic.as_bytes()));
+        }
+    }
+    JsonBytes::from_vec(bloom.to_fixed_bytes().to_vec())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EthExecutionResult {
+    // Gas used after refund
+    pub gas_used: U256,
+    pub logs: Vec<EthLog>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EthLog {
+    pub address: EthAddress,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EthBasicReceipt {
     pub transaction_index: u64,
     pub cumulative_gas: U256,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
 
     pub block_number: u64,
     pub ckb_transaction_hash: H256,
     pub witness_index: u64,
+
+    // Index of the first log in current block
+    pub log_index: u64,
+    pub logs: Vec<EthLog>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Log {
+    pub removed: bool,
+    #[serde(rename = "logIndex")]
+    pub log_index: U256,
+    #[serde(rename = "transactionIndex")]
+    pub transaction_index: U256,
+    #[serde(rename = "transactionHash")]
+    pub transaction_hash: H256,
+    #[serde(rename = "blockHash")]
+    pub block_hash: H256,
+    #[serde(rename = "blockNumber")]
+    pub block_number: U256,
+    pub address: JsonBytes,
+    pub data: JsonBytes,
+    pub topics: Vec<H256>,
+}
+
+impl Log {
+    pub fn from_receipt(
+        basic_receipt: &EthBasicReceipt,
+        transaction_hash: &H256,
+        block_hash: &H256,
+    ) -> Vec<Self> {
+        basic_receipt
+            .logs
+            .iter()
+            .enumerate()
+            .map(|(i, log)| Log {
+                removed: false,
+                log_index: (basic_receipt.log_index + i as u64).into(),
+                transaction_index: basic_receipt.transaction_index.into(),
+                transaction_hash: transaction_hash.clone(),
+                block_hash: block_hash.clone(),
+                block_number: basic_receipt.block_number.into(),
+                address: JsonBytes::from_bytes(log.address.0.clone()),
+                data: JsonBytes::from_bytes(log.data.clone()),
+                topics: log.topics.clone(),
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -465,10 +910,14 @@ pub struct TransactionReceipt {
     pub gas_used: U256,
     #[serde(rename = "contractAddress")]
     pub contract_address: Option<JsonBytes>,
-    pub logs: Vec<JsonBytes>,
+    pub logs: Vec<Log>,
     #[serde(rename = "logsBloom")]
-    pub logs_bloom: H256,
+    pub logs_bloom: JsonBytes,
     pub status: U256,
+    #[serde(rename = "type")]
+    pub transaction_type: U256,
+    #[serde(rename = "effectiveGasPrice")]
+    pub effective_gas_price: U256,
 }
 
 impl TransactionReceipt {
@@ -476,19 +925,27 @@ impl TransactionReceipt {
         basic_receipt: &EthBasicReceipt,
         transaction: &TransactionView,
         block_hash: &H256,
+        chain_id: u64,
     ) -> Result<Self, Error> {
         let witness: Witness = transaction.inner.witnesses[basic_receipt.witness_index as usize]
             .clone()
             .into();
-        let eth_transaction = EthTransaction::from_raw(witness[0].clone())?;
-        let contract_address = transaction
-            .inner
-            .outputs
-            .iter()
-            .find(|output| output.lock.code_hash == CODE_HASH_CONTRACT_LOCK.into())
-            .map(|output| output.lock.args[0].clone());
+        let eth_transaction = EthTransaction::from_raw(witness[0].clone(), chain_id)?;
+        // Message calls might also recreate other contract cells, only
+        // contract creation transactions have contract address.
+        let contract_address = if eth_transaction.to.is_none() {
+            transaction
+                .inner
+                .outputs
+                .iter()
+                .find(|output| output.lock.code_hash == CODE_HASH_CONTRACT_LOCK.into())
+                .map(|output| output.lock.args[0].clone())
+        } else {
+            None
+        };
+        let transaction_hash = eth_transaction.hash();
         Ok(TransactionReceipt {
-            transaction_hash: transaction.hash.clone(),
+            transaction_hash: transaction_hash.clone(),
             transaction_index: basic_receipt.transaction_index.into(),
             block_hash: block_hash.clone(),
             block_number: basic_receipt.block_number.into(),
@@ -498,17 +955,391 @@ impl TransactionReceipt {
                 .clone()
                 .map(|address| JsonBytes::from_bytes(address.0)),
             cumulative_gas_used: basic_receipt.cumulative_gas.clone(),
-            gas_used: eth_transaction.fees()?,
+            gas_used: basic_receipt.gas_used.clone(),
             contract_address,
-            logs: vec![],
-            logs_bloom: H256::zero(),
+            logs: Log::from_receipt(basic_receipt, &transaction_hash, block_hash),
+            logs_bloom: logs_bloom(&basic_receipt.logs),
             status: U256::one(),
+            transaction_type: u64::from(eth_transaction.transaction_type).into(),
+            effective_gas_price: basic_receipt.effective_gas_price.clone(),
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionObject {
+    pub hash: H256,
+    pub nonce: U256,
+    // Block fields are null for transactions not yet committed
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<H256>,
+    #[serde(rename = "blockNumber")]
+    pub block_number: Option<U256>,
+    #[serde(rename = "transactionIndex")]
+    pub transaction_index: Option<U256>,
+    pub from: JsonBytes,
+    pub to: Option<JsonBytes>,
+    pub value: U256,
+    // Effective gas price paid by the transaction
+    #[serde(rename = "gasPrice")]
+    pub gas_price: U256,
+    pub gas: U256,
+    pub input: JsonBytes,
+    pub v: U256,
+    pub r: U256,
+    pub s: U256,
+    #[serde(rename = "type")]
+    pub transaction_type: U256,
+    // Following fields only exist in typed transactions
+    #[serde(rename = "chainId", skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<U256>,
+    #[serde(rename = "accessList", skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<Vec<AccessListItem>>,
+    #[serde(rename = "maxFeePerGas", skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(
+        rename = "maxPriorityFeePerGas",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_priority_fee_per_gas: Option<U256>,
+}
+
+impl TransactionObject {
+    pub fn from(
+        basic_receipt: &EthBasicReceipt,
+        transaction: &TransactionView,
+        block_hash: &H256,
+        chain_id: u64,
+    ) -> Result<Self, Error> {
+        let witness: Witness = transaction.inner.witnesses[basic_receipt.witness_index as usize]
+            .clone()
+            .into();
+        let eth_transaction = EthTransaction::from_raw(witness[0].clone(), chain_id)?;
+        let mut transaction_object = TransactionObject::pending(&eth_transaction, chain_id);
+        transaction_object.block_hash = Some(block_hash.clone());
+        transaction_object.block_number = Some(basic_receipt.block_number.into());
+        transaction_object.transaction_index = Some(basic_receipt.transaction_index.into());
+        transaction_object.gas_price = basic_receipt.effective_gas_price.clone();
+        Ok(transaction_object)
+    }
+
+    // Transaction that is not committed yet
+    pub fn pending(eth_transaction: &EthTransaction, chain_id: u64) -> Self {
+        let typed = eth_transaction.transaction_type != LEGACY_TRANSACTION_TYPE;
+        let dynamic_fee = eth_transaction.transaction_type == DYNAMIC_FEE_TRANSACTION_TYPE;
+        TransactionObject {
+            hash: eth_transaction.hash(),
+            nonce: eth_transaction.nonce.into(),
+            block_hash: None,
+            block_number: None,
+            transaction_index: None,
+            from: JsonBytes::from_bytes(eth_transaction.from.0.clone()),
+            to: eth_transaction
+                .to
+                .clone()
+                .map(|address| JsonBytes::from_bytes(address.0)),
+            value: eth_transaction.value.clone(),
+            gas_price: eth_transaction.effective_gas_price(),
+            gas: eth_transaction.gas_limit.clone(),
+            input: JsonBytes::from_bytes(eth_transaction.data.clone().unwrap_or_default()),
+            v: eth_transaction.v.into(),
+            r: eth_transaction.r.clone(),
+            s: eth_transaction.s.clone(),
+            transaction_type: u64::from(eth_transaction.transaction_type).into(),
+            chain_id: if typed { Some(chain_id.into()) } else { None },
+            access_list: if typed {
+                Some(eth_transaction.access_list.clone())
+            } else {
+                None
+            },
+            max_fee_per_gas: if dynamic_fee {
+                Some(eth_transaction.gas_price.clone())
+            } else {
+                None
+            },
+            max_priority_fee_per_gas: if dynamic_fee {
+                Some(eth_transaction.max_priority_fee_per_gas.clone())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+// Transaction in local transaction pool, along with the CKB transaction
+// carrying it.
+#[derive(Serialize)]
+pub struct PoolTransactionObject {
+    #[serde(flatten)]
+    pub transaction: TransactionObject,
+    #[serde(rename = "ckbTransactionHash")]
+    pub ckb_transaction_hash: Option<H256>,
+    // One of queued, pending, proposed, committed and rejected
+    #[serde(rename = "ckbStatus")]
+    pub ckb_status: String,
+    #[serde(rename = "rejectReason", skip_serializing_if = "Option::is_none")]
+    pub reject_reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TxPoolStatus {
+    pub pending: U256,
+    pub queued: U256,
+}
+
+// Transactions keyed by sender address then by nonce, the same as Geth.
+// Nonces are kept as integers so they are sorted numerically, JSON
+// serialization turns them into decimal strings. Rejected transactions are
+// polyjuice specific.
+#[derive(Serialize)]
+pub struct TxPoolContent<T> {
+    pub pending: BTreeMap<String, BTreeMap<u64, T>>,
+    pub queued: BTreeMap<String, BTreeMap<u64, T>>,
+    pub rejected: BTreeMap<String, BTreeMap<u64, T>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum BlockTransactions {
+    Hashes(Vec<H256>),
+    Full(Vec<TransactionObject>),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Block {
+    pub number: U256,
+    pub hash: H256,
+    #[serde(rename = "parentHash")]
+    pub parent_hash: H256,
+    pub timestamp: U256,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: U256,
+    #[serde(rename = "gasLimit")]
+    pub gas_limit: U256,
+    #[serde(rename = "logsBloom")]
+    pub logs_bloom: JsonBytes,
+    #[serde(rename = "baseFeePerGas")]
+    pub base_fee_per_gas: U256,
+    // Block assembler of the CKB block
+    pub miner: JsonBytes,
+    // Following fields have no real meaning here, they are kept since
+    // Ethereum libraries expect them to be present.
+    #[serde(rename = "extraData")]
+    pub extra_data: JsonBytes,
+    pub difficulty: U256,
+    pub transactions: BlockTransactions,
+}
+
 #[derive(Serialize, Deserialize)]
+pub struct FeeHistory {
+    #[serde(rename = "oldestBlock")]
+    pub oldest_block: U256,
+    // Contains one more item than the number of blocks, which is base fee
+    // of the block after the newest one.
+    #[serde(rename = "baseFeePerGas")]
+    pub base_fee_per_gas: Vec<U256>,
+    #[serde(rename = "gasUsedRatio")]
+    pub gas_used_ratio: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reward: Option<Vec<Vec<U256>>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct EthContractData {
     pub code: Bytes,
     pub storage: HashMap<U256, U256>,
+    // Used to derive addresses of contracts created via CREATE
+    pub nonce: u64,
+}
+
+impl EthContractData {
+    // Contract cells created before contract nonces were tracked only
+    // contain code and storage, those are read with the EIP-161 starting
+    // nonce. Legacy data always runs out of bytes in the current layout, so
+    // the two layouts could not be confused.
+    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        if let Ok(contract_data) = deserialize(data) {
+            return Ok(contract_data);
+        }
+        let legacy_data: LegacyEthContractData = deserialize(data)?;
+        Ok(EthContractData {
+            code: legacy_data.code,
+            storage: legacy_data.storage,
+            nonce: 1,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyEthContractData {
+    code: Bytes,
+    storage: HashMap<U256, U256>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unsigned legacy transaction shared by tests, fields that matter are
+    // set on top of it.
+    pub(super) fn unsigned_transaction() -> EthTransaction {
+        EthTransaction {
+            transaction_type: 0,
+            nonce: 0,
+            gas_price: U256::zero(),
+            max_priority_fee_per_gas: U256::zero(),
+            gas_limit: U256::zero(),
+            to: None,
+            value: U256::zero(),
+            data: None,
+            access_list: vec![],
+            v: 27,
+            r: U256::zero(),
+            s: U256::zero(),
+            from: EthAddress::default(),
+            raw: Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn contract_data_roundtrip() {
+        let mut storage = HashMap::default();
+        storage.insert(U256::one(), U256::from(42u64));
+        let data = EthContractData {
+            code: Bytes::from(&b"code"[..]),
+            storage,
+            nonce: 7,
+        };
+        let decoded = EthContractData::from_slice(&serialize(&data).expect("serialize"))
+            .expect("deserialize");
+        assert!(decoded == data);
+    }
+
+    #[test]
+    fn legacy_contract_data_starts_at_nonce_one() {
+        let mut storage = HashMap::default();
+        storage.insert(U256::one(), U256::from(42u64));
+        let legacy_data = LegacyEthContractData {
+            code: Bytes::from(&b"code"[..]),
+            storage: storage.clone(),
+        };
+        let decoded = EthContractData::from_slice(&serialize(&legacy_data).expect("serialize"))
+            .expect("deserialize");
+        assert_eq!(decoded.code, Bytes::from(&b"code"[..]));
+        assert_eq!(decoded.storage, storage);
+        assert_eq!(decoded.nonce, 1);
+    }
+
+    fn raw(s: &str) -> Bytes {
+        let mut b = vec![0u8; s.len() / 2];
+        hex_decode(s.as_bytes(), &mut b).expect("hex");
+        Bytes::from(b)
+    }
+
+    // Address of private key 0x4646..46 used in EIP-155 example
+    const SENDER: &str = "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f";
+
+    #[test]
+    fn sig_recovery_from_v() {
+        assert!(is_unprotected_v(27));
+        assert!(is_unprotected_v(28));
+        assert!(!is_unprotected_v(37));
+        assert_eq!(calculate_sig_recovery(27, 1).expect("recovery"), 0);
+        assert_eq!(calculate_sig_recovery(28, 1).expect("recovery"), 1);
+        assert_eq!(calculate_sig_recovery(37, 1).expect("recovery"), 0);
+        assert_eq!(calculate_sig_recovery(38, 1).expect("recovery"), 1);
+        assert_eq!(calculate_sig_recovery(2083, 1024).expect("recovery"), 0);
+        assert!(calculate_sig_recovery(39, 1).is_err());
+        assert!(calculate_sig_recovery(36, 1).is_err());
+        assert!(calculate_sig_recovery(0, 1).is_err());
+    }
+
+    #[test]
+    fn decode_eip155_transaction() {
+        // Example from EIP-155
+        let tx = EthTransaction::from_raw(raw("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"), 1).expect("decode");
+        assert_eq!(tx.from, EthAddress::parse(SENDER).expect("address"));
+        assert_eq!(tx.nonce, 9);
+        assert_eq!(tx.v, 37);
+        assert!(!tx.is_unprotected());
+        // Same signature is invalid on another chain
+        let tx = EthTransaction::from_raw(tx.raw, 2);
+        assert!(tx.is_err());
+    }
+
+    #[test]
+    fn decode_unprotected_transaction() {
+        // EIP-155 example fields signed without chain ID
+        let tx = EthTransaction::from_raw(raw("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000801ba08383adc8b8ae116f918fb44ca7ff9dfd8012596a5c130c6246a2cc717ba41cdaa053ddfacf5bd4aa7e46d1575acf52636ea659b91f29e2fb91c75567a279738f38"), 1).expect("decode");
+        assert_eq!(tx.from, EthAddress::parse(SENDER).expect("address"));
+        assert_eq!(tx.v, 27);
+        assert!(tx.is_unprotected());
+    }
+
+    // Typed transactions signed by SENDER on chain 1: nonce 9, gas limit
+    // 30000, 1 ETH to 0x3535..35 with data 0x1234 and an access list of
+    // 0x3535..35 with storage key 0.
+    const ACCESS_LIST_TRANSACTION: &str = "01f8a901098504a817c800827530943535353535353535353535353535353535353535880de0b6b3a7640000821234f838f7943535353535353535353535353535353535353535e1a0000000000000000000000000000000000000000000000000000000000000000080a007df3fdeae4ed40360d3abf1f225017de8dcf45f53a7702de12b863032aa5118a05c23e9492394262960809c18272118f4c4d7e71f4ee34fc75c460fa7f348c644";
+    const DYNAMIC_FEE_TRANSACTION: &str = "02f8ae0109843b9aca008504a817c800827530943535353535353535353535353535353535353535880de0b6b3a7640000821234f838f7943535353535353535353535353535353535353535e1a0000000000000000000000000000000000000000000000000000000000000000001a09297758607c27e79ef8a242da3d0dc8a734f58db191ad311d4d6c1e93ca526dea043fe606b8fedac1cebf6473ba83ae1257314bc1cc078b29e59e1a0d762b9c61b";
+
+    #[test]
+    fn decode_access_list_transaction() {
+        let tx = EthTransaction::from_raw(raw(ACCESS_LIST_TRANSACTION), 1).expect("decode");
+        assert_eq!(tx.transaction_type, ACCESS_LIST_TRANSACTION_TYPE);
+        assert_eq!(tx.from, EthAddress::parse(SENDER).expect("address"));
+        assert_eq!(tx.nonce, 9);
+        assert_eq!(tx.gas_price, U256::from(20_000_000_000u64));
+        assert_eq!(tx.max_priority_fee_per_gas, U256::from(20_000_000_000u64));
+        assert_eq!(tx.gas_limit, U256::from(30_000u64));
+        assert_eq!(tx.data, Some(Bytes::from(&[0x12u8, 0x34][..])));
+        assert_eq!(tx.access_list.len(), 1);
+        assert_eq!(tx.access_list[0].storage_keys, vec![H256::zero()]);
+        assert_eq!(tx.v, 0);
+        assert!(!tx.is_unprotected());
+    }
+
+    #[test]
+    fn decode_dynamic_fee_transaction() {
+        let tx = EthTransaction::from_raw(raw(DYNAMIC_FEE_TRANSACTION), 1).expect("decode");
+        assert_eq!(tx.transaction_type, DYNAMIC_FEE_TRANSACTION_TYPE);
+        assert_eq!(tx.from, EthAddress::parse(SENDER).expect("address"));
+        assert_eq!(tx.nonce, 9);
+        assert_eq!(tx.gas_price, U256::from(20_000_000_000u64));
+        assert_eq!(tx.max_priority_fee_per_gas, U256::from(1_000_000_000u64));
+        assert_eq!(tx.value, U256::from(1_000_000_000_000_000_000u64));
+        assert_eq!(tx.access_list.len(), 1);
+        assert_eq!(tx.v, 1);
+    }
+
+    #[test]
+    fn reject_typed_transaction_of_other_chain() {
+        assert!(EthTransaction::from_raw(raw(ACCESS_LIST_TRANSACTION), 2).is_err());
+        assert!(EthTransaction::from_raw(raw(DYNAMIC_FEE_TRANSACTION), 2).is_err());
+        // Unknown transaction type
+        let mut unknown = raw(DYNAMIC_FEE_TRANSACTION).to_vec();
+        unknown[0] = 3;
+        assert!(EthTransaction::from_raw(Bytes::from(unknown), 1).is_err());
+    }
+
+    #[test]
+    fn txpool_content_sorts_nonces_numerically() {
+        let mut transactions = BTreeMap::new();
+        for nonce in &[10u64, 9, 100] {
+            transactions.insert(*nonce, nonce.to_string());
+        }
+        let mut pending = BTreeMap::new();
+        pending.insert(SENDER.to_string(), transactions);
+        let content = TxPoolContent {
+            pending,
+            queued: BTreeMap::new(),
+            rejected: BTreeMap::new(),
+        };
+        assert_eq!(
+            serde_json::to_string(&content).expect("serialize"),
+            format!(
+                r#"{{"pending":{{"{}":{{"9":"9","10":"10","100":"100"}}}},"queued":{{}},"rejected":{{}}}}"#,
+                SENDER
+            )
+        );
+    }
 }
@@ -0,0 +1,398 @@
+use super::EthAddress;
+use crate::Error;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use ethcore_builtin::Builtin;
+use ethereum_types::U256 as ParityU256;
+use lazy_static::lazy_static;
+use parity_bytes::BytesRef;
+use serde_json::json;
+use std::cmp;
+
+// Gas pricing follows Istanbul, which is a superset of Constantinople
+// precompiles with EIP-1108 alt_bn128 repricing and EIP-152 blake2_f.
+lazy_static! {
+    static ref PRECOMPILES: Vec<Precompile> = vec![
+        Precompile::builtin(
+            "ecrecover",
+            Pricing::Linear {
+                base: 3000,
+                word: 0
+            }
+        ),
+        Precompile::builtin("sha256", Pricing::Linear { base: 60, word: 12 }),
+        Precompile::builtin(
+            "ripemd160",
+            Pricing::Linear {
+                base: 600,
+                word: 120
+            }
+        ),
+        Precompile::builtin("identity", Pricing::Linear { base: 15, word: 3 }),
+        Precompile::builtin("modexp", Pricing::Modexp { divisor: 20 }),
+        Precompile::builtin("alt_bn128_add", Pricing::Linear { base: 150, word: 0 }),
+        Precompile::builtin(
+            "alt_bn128_mul",
+            Pricing::Linear {
+                base: 6000,
+                word: 0
+            }
+        ),
+        Precompile::builtin(
+            "alt_bn128_pairing",
+            Pricing::AltBn128Pairing {
+                base: 45000,
+                pair: 34000,
+            },
+        ),
+        Precompile {
+            name: "blake2_f",
+            pricing: Pricing::Blake2F { gas_per_round: 1 },
+            native: None,
+        },
+    ];
+}
+
+enum Pricing {
+    // base + word * words of input
+    Linear { base: u64, word: u64 },
+    // EIP-198
+    Modexp { divisor: u64 },
+    // base + pair * number of (G1, G2) pairs
+    AltBn128Pairing { base: u64, pair: u64 },
+    // EIP-152, rounds are given in the first 4 bytes of input
+    Blake2F { gas_per_round: u64 },
+}
+
+pub struct Precompile {
+    pub name: &'static str,
+    pricing: Pricing,
+    // Parity builtin doing the actual work, blake2_f is implemented here
+    // since the vendored parity version predates Istanbul.
+    native: Option<Builtin>,
+}
+
+impl Precompile {
+    // Parity builtins are only used for execution, pricing is handled here
+    // so the builtin is created with a placeholder linear pricing.
+    fn builtin(name: &'static str, pricing: Pricing) -> Self {
+        let spec: ethjson::spec::Builtin = serde_json::from_value(json!({
+            "name": name,
+            "pricing": { "linear": { "base": 0, "word": 0 } },
+        }))
+        .expect("parse builtin spec");
+        Precompile {
+            name,
+            pricing,
+            native: Some(Builtin::from(spec)),
+        }
+    }
+
+    // Precompiles live at address 0x01 to 0x09
+    pub fn from_address(address: &EthAddress) -> Option<&'static Precompile> {
+        let bytes = &address.0;
+        if bytes.len() != 20 || bytes[..19].iter().any(|b| *b != 0) || bytes[19] == 0 {
+            return None;
+        }
+        PRECOMPILES.get(bytes[19] as usize - 1)
+    }
+
+    pub fn cost(&self, input: &[u8]) -> ParityU256 {
+        match self.pricing {
+            Pricing::Linear { base, word } => {
+                let words = (input.len() as u64 + 31) / 32;
+                ParityU256::from(base) + ParityU256::from(word) * ParityU256::from(words)
+            }
+            Pricing::Modexp { divisor } => modexp_cost(input, divisor),
+            Pricing::AltBn128Pairing { base, pair } => {
+                let pairs = input.len() as u64 / 192;
+                ParityU256::from(base) + ParityU256::from(pair) * ParityU256::from(pairs)
+            }
+            Pricing::Blake2F { gas_per_round } => {
+                if input.len() != BLAKE2_F_INPUT_LENGTH {
+                    return ParityU256::zero();
+                }
+                ParityU256::from(gas_per_round) * ParityU256::from(BigEndian::read_u32(input))
+            }
+        }
+    }
+
+    pub fn execute(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        match &self.native {
+            Some(builtin) => {
+                let mut output = Vec::new();
+                builtin
+                    .execute(input, &mut BytesRef::Flexible(&mut output))
+                    .map_err(|e| Error::EVM(format!("{} error: {:?}", self.name, e)))?;
+                Ok(output)
+            }
+            None => blake2_f(input),
+        }
+    }
+}
+
+fn read_u256(input: &[u8], offset: usize) -> ParityU256 {
+    let mut buf = [0u8; 32];
+    if offset < input.len() {
+        let end = cmp::min(input.len(), offset + 32);
+        buf[..end - offset].copy_from_slice(&input[offset..end]);
+    }
+    ParityU256::from_big_endian(&buf)
+}
+
+fn modexp_cost(input: &[u8], divisor: u64) -> ParityU256 {
+    let base_len = read_u256(input, 0);
+    let exp_len = read_u256(input, 32);
+    let mod_len = read_u256(input, 64);
+    if base_len.is_zero() && mod_len.is_zero() {
+        return ParityU256::zero();
+    }
+    let max_len = ParityU256::from(u32::max_value() / 2);
+    if base_len > max_len || exp_len > max_len || mod_len > max_len {
+        return ParityU256::max_value();
+    }
+    let (base_len, exp_len, mod_len) = (base_len.low_u64(), exp_len.low_u64(), mod_len.low_u64());
+    // Only the first 32 bytes of exponent are used in pricing
+    let exp_offset = 96 + base_len as usize;
+    let exp_head_len = cmp::min(exp_len, 32) as usize;
+    let mut buf = [0u8; 32];
+    if exp_offset < input.len() {
+        let end = cmp::min(input.len(), exp_offset + exp_head_len);
+        buf[32 - exp_head_len..32 - exp_head_len + end - exp_offset]
+            .copy_from_slice(&input[exp_offset..end]);
+    }
+    let exp_head = ParityU256::from_big_endian(&buf);
+    let bit_index = if exp_head.is_zero() {
+        0
+    } else {
+        255 - u64::from(exp_head.leading_zeros())
+    };
+    let adjusted_exp_len = if exp_len <= 32 {
+        bit_index
+    } else {
+        8 * (exp_len - 32) + bit_index
+    };
+    let x = cmp::max(base_len, mod_len);
+    let mult_complexity = if x <= 64 {
+        x * x
+    } else if x <= 1024 {
+        x * x / 4 + 96 * x - 3072
+    } else {
+        x * x / 16 + 480 * x - 199680
+    };
+    match mult_complexity.checked_mul(cmp::max(adjusted_exp_len, 1)) {
+        Some(gas) => ParityU256::from(gas / divisor),
+        None => ParityU256::max_value(),
+    }
+}
+
+const BLAKE2_F_INPUT_LENGTH: usize = 213;
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+#[allow(clippy::many_single_char_names)]
+fn blake2b_g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+// Input layout: rounds (4 bytes big endian), h (64 bytes), m (128 bytes),
+// t (16 bytes) and final block flag (1 byte), all words are little endian.
+fn blake2_f(input: &[u8]) -> Result<Vec<u8>, Error> {
+    if input.len() != BLAKE2_F_INPUT_LENGTH {
+        return Err(Error::EVM(format!(
+            "blake2_f error: invalid input length {}",
+            input.len()
+        )));
+    }
+    let last_block = match input[212] {
+        0 => false,
+        1 => true,
+        flag => {
+            return Err(Error::EVM(format!(
+                "blake2_f error: invalid final block flag {}",
+                flag
+            )))
+        }
+    };
+    let rounds = BigEndian::read_u32(&input[0..4]) as usize;
+    let mut h = [0u64; 8];
+    LittleEndian::read_u64_into(&input[4..68], &mut h);
+    let mut m = [0u64; 16];
+    LittleEndian::read_u64_into(&input[68..196], &mut m);
+    let mut t = [0u64; 2];
+    LittleEndian::read_u64_into(&input[196..212], &mut t);
+
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(&h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= t[0];
+    v[13] ^= t[1];
+    if last_block {
+        v[14] = !v[14];
+    }
+    for round in 0..rounds {
+        let s = &BLAKE2B_SIGMA[round % 10];
+        blake2b_g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+        blake2b_g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+        blake2b_g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+        blake2b_g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+        blake2b_g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+        blake2b_g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+        blake2b_g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+        blake2b_g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+    for (i, word) in h.iter_mut().enumerate() {
+        *word ^= v[i] ^ v[i + 8];
+    }
+    let mut output = vec![0u8; 64];
+    LittleEndian::write_u64_into(&h, &mut output);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faster_hex::hex_decode;
+
+    fn raw(s: &str) -> Vec<u8> {
+        let mut b = vec![0u8; s.len() / 2];
+        hex_decode(s.as_bytes(), &mut b).expect("hex");
+        b
+    }
+
+    // EIP-152 test vector 5 without the rounds prefix
+    const BLAKE2_F_STATE: &str = "48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001";
+
+    fn blake2_f_input(rounds: &str, last_block: bool) -> Vec<u8> {
+        let mut input = raw(rounds);
+        input.extend(raw(BLAKE2_F_STATE));
+        if !last_block {
+            input[212] = 0;
+        }
+        input
+    }
+
+    #[test]
+    fn blake2_f_eip152_vectors() {
+        let vectors = vec![
+            (
+                blake2_f_input("00000000", true),
+                "08c9bcf367e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d282e6ad7f520e511f6c3e2b8c68059b9442be0454267ce079217e1319cde05b",
+            ),
+            (
+                blake2_f_input("0000000c", true),
+                "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            ),
+            (
+                blake2_f_input("0000000c", false),
+                "75ab69d3190a562c51aef8d88f1c2775876944407270c42c9844252c26d2875298743e7f6d5ea2f2d3e8d226039cd31b4e426ac4f2d3d666a610c2116fde4735",
+            ),
+            (
+                blake2_f_input("00000001", true),
+                "b63a380cb2897d521994a85234ee2c181b5f844d2c624c002677e9703449d2fba551b3a8333bcdf5f2f7e08993d53923de3d64fcc68c034e717b9293fed7a421",
+            ),
+        ];
+        for (input, output) in vectors {
+            assert_eq!(blake2_f(&input).expect("blake2_f"), raw(output));
+        }
+    }
+
+    #[test]
+    fn blake2_f_invalid_input() {
+        let input = blake2_f_input("0000000c", true);
+        assert!(blake2_f(&[]).is_err());
+        assert!(blake2_f(&input[..212]).is_err());
+        let mut longer = input.clone();
+        longer.push(0);
+        assert!(blake2_f(&longer).is_err());
+        let mut invalid_flag = input;
+        invalid_flag[212] = 2;
+        assert!(blake2_f(&invalid_flag).is_err());
+    }
+
+    fn modexp_input(base_len: u64, exp_len: u64, mod_len: u64, data: &[u8]) -> Vec<u8> {
+        let mut input = vec![0u8; 96];
+        for (i, len) in [base_len, exp_len, mod_len].iter().enumerate() {
+            BigEndian::write_u64(&mut input[i * 32 + 24..i * 32 + 32], *len);
+        }
+        input.extend_from_slice(data);
+        input
+    }
+
+    #[test]
+    fn modexp_cost_eip198_vectors() {
+        // Examples from EIP-198
+        let input = modexp_input(
+            1,
+            32,
+            32,
+            &raw("03fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2efffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"),
+        );
+        assert_eq!(modexp_cost(&input, 20), ParityU256::from(13056));
+        let input = modexp_input(
+            0,
+            32,
+            32,
+            &raw("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2efffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"),
+        );
+        assert_eq!(modexp_cost(&input, 20), ParityU256::from(13056));
+        // Zero exponent is priced as a single iteration
+        assert_eq!(
+            modexp_cost(&modexp_input(1, 1, 1, &raw("030005")), 20),
+            ParityU256::zero()
+        );
+        assert_eq!(
+            modexp_cost(&modexp_input(64, 0, 64, &[]), 20),
+            ParityU256::from(204)
+        );
+        // Exponents longer than 32 bytes, missing input is zero padded
+        assert_eq!(
+            modexp_cost(&modexp_input(1, 64, 1, &raw("0301")), 20),
+            ParityU256::from(25)
+        );
+        assert_eq!(
+            modexp_cost(&modexp_input(100, 1, 0, &[]), 20),
+            ParityU256::from(451)
+        );
+        assert_eq!(
+            modexp_cost(&modexp_input(2000, 1, 0, &[]), 20),
+            ParityU256::from(50516)
+        );
+        assert_eq!(
+            modexp_cost(&modexp_input(0, 32, 0, &raw("ff")), 20),
+            ParityU256::zero()
+        );
+        assert_eq!(
+            modexp_cost(&modexp_input(u64::max_value(), 1, 1, &[]), 20),
+            ParityU256::max_value()
+        );
+    }
+}
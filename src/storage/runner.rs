@@ -1,27 +1,78 @@
 use super::{
-    CellType, Error, EthAccount, EthAddress, EthCell, EthContractData, EthTransaction, Loader,
+    build_lock_script, capacity_to_wei, precompile::Precompile, u256_to_u64, wei_to_capacity,
+    CellType, Error, EthAccount, EthAddress, EthCell, EthContractData, EthExecutionResult, EthLog,
+    EthTransaction, Loader, PendingCells, BLOCK_GAS_LIMIT, RESULT_WITNESS_FLAG,
 };
 use crate::{CODE_HASH_CONTRACT_LOCK, CODE_HASH_LOCK};
 use bincode::serialize;
 use bytes::{Bytes, BytesMut};
 use ckb_core::transaction::CellOutput as CoreCellOutput;
 use ckb_jsonrpc_types::{
-    Capacity, CellInput, CellOutput, JsonBytes, OutPoint, Script, Transaction, Unsigned, Version,
+    Capacity, CellInput, CellOutPoint, CellOutput, JsonBytes, OutPoint, Transaction, Unsigned,
+    Version,
 };
-use ckb_occupied_capacity::AsCapacity;
+use ckb_occupied_capacity::{AsCapacity, Capacity as CoreCapacity};
 use ethereum_types::{Address as ParityAddress, H256 as ParityH256, U256 as ParityU256};
 use evm::Factory;
+use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use rlp::RlpStream;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tiny_keccak::keccak256;
 use vm::{
-    ActionParams, ActionValue, CallType, ContractCreateResult, CreateContractAddress, EnvInfo, Ext,
-    GasLeft, MessageCallResult, ParamsType, Result as ParityVmResult, ReturnData, Schedule,
-    TrapKind,
+    ActionParams, ActionValue, CallType, ContractCreateResult, CreateContractAddress, EnvInfo,
+    Error as ParityVmError, Ext, GasLeft, MessageCallResult, ParamsType, Result as ParityVmResult,
+    ReturnData, Schedule, TrapKind,
 };
 
+// EIP-2028 calldata cost of non-zero bytes
+const TX_DATA_NON_ZERO_GAS: usize = 16;
+// EIP-2930 access list costs
+const ACCESS_LIST_ADDRESS_GAS: usize = 2400;
+const ACCESS_LIST_STORAGE_KEY_GAS: usize = 1900;
+// BLOCKHASH only returns hashes of the most recent blocks
+const BLOCKHASH_WINDOW: u64 = 256;
+
+// Constantinople rules with SELFBALANCE from EIP-1884 enabled
+fn evm_schedule() -> Schedule {
+    let mut schedule = Schedule::new_constantinople();
+    schedule.have_selfbalance = true;
+    schedule
+}
+
+// Gas charged before any code is executed: base transaction cost plus
+// cost of transaction data and EIP-2930 access list, priced as in Berlin.
+// Access lists are only charged here, the Constantinople schedule has no
+// warm or cold storage access.
+fn intrinsic_gas(tx: &EthTransaction) -> U256 {
+    let schedule = evm_schedule();
+    let base_gas = if tx.to.is_none() {
+        schedule.tx_create_gas
+    } else {
+        schedule.tx_gas
+    };
+    let data_gas: usize = tx
+        .data
+        .iter()
+        .flat_map(|data| data.iter())
+        .map(|b| {
+            if *b == 0 {
+                schedule.tx_data_zero_gas
+            } else {
+                TX_DATA_NON_ZERO_GAS
+            }
+        })
+        .sum();
+    let access_list_gas: usize = tx
+        .access_list
+        .iter()
+        .map(|item| ACCESS_LIST_ADDRESS_GAS + item.storage_keys.len() * ACCESS_LIST_STORAGE_KEY_GAS)
+        .sum();
+    U256::from((base_gas + data_gas + access_list_gas) as u64)
+}
+
 fn numext_u256_to_parity_h256(v: &U256) -> ParityH256 {
     ParityH256::from_slice(&v.to_be_bytes())
 }
@@ -30,164 +81,578 @@ fn parity_h256_to_numext_u256(v: &ParityH256) -> U256 {
     U256::from_be_bytes(&v.to_fixed_bytes())
 }
 
+fn parity_h256_to_numext_h256(v: &ParityH256) -> H256 {
+    v.to_fixed_bytes().into()
+}
+
 fn to_parity_u256(v: &U256) -> ParityU256 {
     ParityU256::from_little_endian(&v.to_le_bytes())
 }
 
+fn from_parity_u256(v: &ParityU256) -> U256 {
+    let mut bytes = [0u8; 32];
+    v.to_little_endian(&mut bytes);
+    U256::from_le_bytes(&bytes)
+}
+
+// A contract touched during current execution
+#[derive(Clone)]
+struct ContractState {
+    // None for contracts created in current transaction
+    main_cell: Option<EthCell>,
+    data: EthContractData,
+    capacity: Capacity,
+}
+
+impl ContractState {
+    fn created(capacity: Capacity) -> Self {
+        ContractState {
+            main_cell: None,
+            data: EthContractData {
+                code: Bytes::default(),
+                storage: HashMap::default(),
+                // EIP-161: contract nonce starts at 1
+                nonce: 1,
+            },
+            capacity,
+        }
+    }
+}
+
+// Execution state that can be reverted when a nested call fails
+#[derive(Clone, Default)]
+struct State {
+    contracts: HashMap<EthAddress, ContractState>,
+    // Capacities sent by contracts to normal accounts, each of them becomes
+    // a new fund cell of the receiver, except for the sender whose part is
+    // added to the change cell.
+    transfers: HashMap<EthAddress, Capacity>,
+    // Contracts destroyed by SELFDESTRUCT, they stay callable until the end
+    // of current transaction.
+    destroyed: HashSet<EthAddress>,
+    logs: Vec<EthLog>,
+    // Gas refund from SSTORE and SELFDESTRUCT
+    refund: i128,
+}
+
 pub struct Runner<'a> {
     pub loader: &'a Loader,
     pub tx: &'a EthTransaction,
     pub block_number: u64,
+    // When present, the transaction is built on top of pending transactions
+    pub pending_cells: Option<&'a PendingCells>,
+
+    state: State,
+    // Gas used by current transaction after refund, sender only pays for
+    // this part of gas.
+    gas_used: U256,
+    // Contract data at the start of current transaction, this is used to
+    // calculate SSTORE gas cost as well as to skip unchanged contracts.
+    initial_contracts: HashMap<EthAddress, EthContractData>,
+    // Block environment built from the block at block_number
+    env_info: EnvInfo,
 }
 
 impl<'a> Runner<'a> {
+    pub fn new(loader: &'a Loader, tx: &'a EthTransaction, block_number: u64) -> Self {
+        Runner {
+            loader,
+            tx,
+            block_number,
+            pending_cells: None,
+            state: State::default(),
+            gas_used: U256::zero(),
+            initial_contracts: HashMap::default(),
+            env_info: EnvInfo::default(),
+        }
+    }
+
+    pub fn with_pending_cells(mut self, pending_cells: &'a PendingCells) -> Self {
+        self.pending_cells = Some(pending_cells);
+        self
+    }
+
     pub fn run(&mut self) -> Result<Transaction, Error> {
-        if self.tx.to.is_none() {
-            self.create_contract()
-        } else {
-            let to = self.load_account()?;
-            if to.contract_account()? {
-                self.call_contract(&to)
+        let mut ckb_transaction = match self.execute()? {
+            Some(contract_address) => {
+                let (inputs, mut outputs) = self.build_contract_cells(&contract_address)?;
+                outputs.extend(self.build_transfer_cells());
+                self.build_ckb_transaction(inputs, outputs)?
+            }
+            None => self.send_to_normal_account()?,
+        };
+        let execution_result = EthExecutionResult {
+            gas_used: self.gas_used.clone(),
+            logs: self.state.logs.clone(),
+        };
+        let result_witness = vec![
+            Bytes::from(RESULT_WITNESS_FLAG),
+            Bytes::from(serialize(&execution_result)?),
+        ];
+        ckb_transaction.witnesses.push((&result_witness).into());
+        Ok(ckb_transaction)
+    }
+
+    // Binary searches the lowest gas limit the transaction succeeds with,
+    // the same way as Geth. Gas used alone is not enough since refunds are
+    // deducted from it, and nested calls only get 63/64 of gas left.
+    pub fn estimate_gas(&self) -> Result<U256, Error> {
+        let mut high = u256_to_u64(&self.tx.gas_limit)?;
+        // Failures at the highest gas limit, such as reverts, are returned
+        // as they are.
+        let gas_used = u256_to_u64(&self.execute_with_gas_limit(high)?)?;
+        // Both intrinsic gas and gas used after refund are needed at least
+        let mut low = cmp::max(u256_to_u64(&intrinsic_gas(self.tx))?, gas_used) - 1;
+        while low + 1 < high {
+            let middle = low + (high - low) / 2;
+            if self.execute_with_gas_limit(middle).is_ok() {
+                high = middle;
             } else {
-                self.send_to_normal_account()
+                low = middle;
             }
         }
+        Ok(U256::from(high))
     }
 
-    pub fn call(&mut self) -> Result<Bytes, Error> {
-        let to = self.load_account()?;
-        if to.contract_account()? {
-            let contract_address = self.tx.to.clone().unwrap();
-            let contract_data = to.contract_data()?;
-            let (_, return_data, _) = self.call_evm(&contract_address, contract_data)?;
-            if return_data.is_none() {
-                return Err(Error::MalformedData("No output data!".to_string()));
-            }
-            Ok(Bytes::from(&*return_data.unwrap()))
-        } else {
-            Err(Error::MalformedData("Calling on EOA!".to_string()))
-        }
+    // Executes a copy of the transaction with a different gas limit on top
+    // of the same state, returns gas used including intrinsic gas.
+    fn execute_with_gas_limit(&self, gas_limit: u64) -> Result<U256, Error> {
+        let mut tx = self.tx.clone();
+        tx.gas_limit = U256::from(gas_limit);
+        let mut runner = Runner::new(self.loader, &tx, self.block_number);
+        runner.pending_cells = self.pending_cells;
+        runner.execute()?;
+        Ok(runner.gas_used)
     }
 
-    fn load_account(&self) -> Result<EthAccount, Error> {
-        let to_address = self.tx.to.clone().unwrap();
-        self.loader
-            .load_account(&to_address, self.block_number, false)?
-            .ok_or(Error::MalformedData(
-                "Contract does not exist yet!".to_string(),
-            ))
+    pub fn call(&mut self) -> Result<Bytes, Error> {
+        self.load_env_info()?;
+        let contract_address = self
+            .tx
+            .to
+            .clone()
+            .ok_or(Error::MalformedData("Missing target address!".to_string()))?;
+        match self.load_contract(&contract_address)? {
+            Some(code) => {
+                let value_capacity = self.tx.value_in_capacity()?;
+                self.deposit(&contract_address, &value_capacity)?;
+                let (_, return_data) = self.call_evm(&contract_address, code)?;
+                if return_data.is_none() {
+                    return Err(Error::MalformedData("No output data!".to_string()));
+                }
+                Ok(Bytes::from(&*return_data.unwrap()))
+            }
+            None => Err(Error::MalformedData("Calling on EOA!".to_string())),
+        }
     }
 
-    fn send_to_normal_account(&self) -> Result<Transaction, Error> {
-        // TODO: check gas limit
-        let data = JsonBytes::default();
-        let mut lock = Script::default();
-        lock.code_hash = CODE_HASH_LOCK.into();
-        lock.args
-            .push(JsonBytes::from_bytes(self.tx.to.clone().unwrap().0));
-
-        self.build_ckb_transaction(data, lock, Capacity(0u64.as_capacity()))
-    }
-
-    fn call_contract(&mut self, contract_account: &EthAccount) -> Result<Transaction, Error> {
-        let contract_address = self.tx.to.clone().unwrap();
-        let contract_data = contract_account.contract_data()?;
-        let (_, _, contract_data) = self.call_evm(&contract_address, contract_data)?;
-
-        let mut data = BytesMut::from(&[CellType::ContractMainCell as u8][..]);
-        data.extend_from_slice(&serialize(&contract_data)?);
-        let data = JsonBytes::from_bytes(data.freeze());
-        let mut lock = Script::default();
-        lock.code_hash = CODE_HASH_CONTRACT_LOCK.into();
-        lock.args
-            .push(JsonBytes::from_bytes(contract_address.0.clone()));
-
-        let EthCell(main_cell_output, main_cell_out_point) = contract_account
+    // Loads a contract account into execution state if it is not there yet,
+    // returns contract code, or None if the address is not a contract.
+    fn load_contract(&mut self, address: &EthAddress) -> Result<Option<Bytes>, Error> {
+        if let Some(contract) = self.state.contracts.get(address) {
+            return Ok(Some(contract.data.code.clone()));
+        }
+        let account = match self.load_account(address)? {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        if !account.contract_account()? {
+            return Ok(None);
+        }
+        let data = account.contract_data()?;
+        let code = data.code.clone();
+        let main_cell = account
             .main_cell
             .clone()
             .expect("contract account must have main cell");
-        let mut ckb_transaction =
-            self.build_ckb_transaction(data, lock, main_cell_output.capacity)?;
-        ckb_transaction.inputs.push(CellInput {
-            previous_output: OutPoint {
-                cell: Some(main_cell_out_point),
-                block_hash: None,
+        self.initial_contracts.insert(address.clone(), data.clone());
+        self.state.contracts.insert(
+            address.clone(),
+            ContractState {
+                capacity: main_cell.0.capacity.clone(),
+                main_cell: Some(main_cell),
+                data,
             },
-            since: Unsigned(0),
-        });
-        ckb_transaction.witnesses.push((&vec![]).into());
-        Ok(ckb_transaction)
+        );
+        Ok(Some(code))
     }
 
-    fn create_contract(&mut self) -> Result<Transaction, Error> {
+    // Balance at current point of execution
+    fn balance(&self, address: &EthAddress) -> Result<U256, Error> {
+        if let Some(contract) = self.state.contracts.get(address) {
+            return capacity_to_wei(&contract.capacity);
+        }
+        let mut capacity = match self.load_account(address)? {
+            Some(account) => account.total_capacities()?,
+            None => Capacity(0u64.as_capacity()),
+        };
+        if let Some(transferred) = self.state.transfers.get(address) {
+            capacity = Capacity(
+                capacity
+                    .0
+                    .safe_add(transferred.0)
+                    .map_err(|_| Error::MalformedData("Capacity overflow".to_string()))?,
+            );
+        }
+        // Sender pays for the whole gas limit and value before execution
+        if address == &self.tx.from {
+            let fees_capacity = self.tx.fees_in_capacity()?;
+            let value_capacity = self.tx.value_in_capacity()?;
+            capacity = Capacity(
+                capacity
+                    .0
+                    .safe_sub(fees_capacity.0)
+                    .and_then(|c| c.safe_sub(value_capacity.0))
+                    .unwrap_or_else(|_| 0u64.as_capacity()),
+            );
+        }
+        capacity_to_wei(&capacity)
+    }
+
+    // Code of an account without loading it into execution state, normal
+    // accounts have empty code, returns None if the account does not exist.
+    fn load_code(&self, address: &EthAddress) -> Result<Option<Bytes>, Error> {
+        if let Some(contract) = self.state.contracts.get(address) {
+            return Ok(Some(contract.data.code.clone()));
+        }
+        if let Some(account) = self.load_account(address)? {
+            if account.contract_account()? {
+                return Ok(Some(account.contract_data()?.code));
+            }
+            if account.main_cell.is_some() || !account.fund_cells.is_empty() {
+                return Ok(Some(Bytes::default()));
+            }
+        }
+        if self.state.transfers.contains_key(address) {
+            return Ok(Some(Bytes::default()));
+        }
+        Ok(None)
+    }
+
+    fn load_account(&self, address: &EthAddress) -> Result<Option<EthAccount>, Error> {
+        match self.pending_cells {
+            // Cells spent on chain but not indexed yet are either consumed
+            // by pending transactions or will be rejected by CKB anyway.
+            Some(pending_cells) => {
+                match self.loader.load_account(address, self.block_number, true)? {
+                    Some(account) => Ok(Some(pending_cells.apply(address, account)?)),
+                    None => Ok(None),
+                }
+            }
+            None => self.loader.load_account(address, self.block_number, false),
+        }
+    }
+
+    // Transactions are executed in the environment of the block whose state
+    // they are built upon, and could not use more gas than the block allows.
+    fn load_env_info(&mut self) -> Result<(), Error> {
+        let block_env =
+            self.loader
+                .load_block_env(self.block_number)?
+                .ok_or(Error::MalformedData(format!(
+                    "Block {} is not indexed!",
+                    self.block_number
+                )))?;
+        if self.tx.gas_limit > U256::from(BLOCK_GAS_LIMIT) {
+            return Err(Error::MalformedData(format!(
+                "Gas limit exceeds block gas limit: {}",
+                BLOCK_GAS_LIMIT
+            )));
+        }
+        self.env_info = EnvInfo {
+            number: self.block_number,
+            author: (&block_env.author).into(),
+            timestamp: block_env.timestamp,
+            difficulty: to_parity_u256(&block_env.difficulty),
+            gas_limit: ParityU256::from(BLOCK_GAS_LIMIT),
+            ..EnvInfo::default()
+        };
+        Ok(())
+    }
+
+    // Calculates gas used from gas left after execution, applying refunds
+    // capped at half of gas used.
+    fn finalize_gas(&mut self, gas_left: &ParityU256) {
+        let gas_used = to_parity_u256(&self.tx.gas_limit) - *gas_left;
+        let refund = if self.state.refund > 0 {
+            ParityU256::from(self.state.refund as u64)
+        } else {
+            ParityU256::zero()
+        };
+        let refund = cmp::min(refund, gas_used / ParityU256::from(2));
+        self.gas_used = from_parity_u256(&(gas_used - refund));
+    }
+
+    // Runs EVM for contract creation or contract call, and updates gas used.
+    // Returns the target contract address, or None if the transaction is a
+    // plain transfer to a normal account.
+    fn execute(&mut self) -> Result<Option<EthAddress>, Error> {
+        self.load_env_info()?;
+        let intrinsic_gas = intrinsic_gas(self.tx);
+        if self.tx.gas_limit < intrinsic_gas {
+            return Err(Error::MalformedData(format!(
+                "Gas limit is lower than intrinsic gas: {}",
+                intrinsic_gas
+            )));
+        }
+        if self.tx.to.is_none() {
+            return self.create_contract().map(Some);
+        }
+        let to = self.tx.to.clone().unwrap();
+        match self.load_contract(&to)? {
+            Some(code) => {
+                self.call_contract(&to, code)?;
+                Ok(Some(to))
+            }
+            None => {
+                self.gas_used = intrinsic_gas;
+                Ok(None)
+            }
+        }
+    }
+
+    fn send_to_normal_account(&self) -> Result<Transaction, Error> {
+        let lock = build_lock_script(
+            CODE_HASH_LOCK,
+            self.tx.to.as_ref().unwrap(),
+            self.loader.chain_id,
+        );
+        let target_cell = CoreCellOutput {
+            capacity: self.tx.value_in_capacity()?.0,
+            data: Bytes::default(),
+            lock: lock.into(),
+            type_: None,
+        };
+
+        self.build_ckb_transaction(vec![], vec![target_cell])
+    }
+
+    fn call_contract(&mut self, contract_address: &EthAddress, code: Bytes) -> Result<(), Error> {
+        let value_capacity = self.tx.value_in_capacity()?;
+        self.deposit(contract_address, &value_capacity)?;
+        let (gas_left, _) = self.call_evm(contract_address, code)?;
+        self.finalize_gas(&gas_left);
+        Ok(())
+    }
+
+    fn create_contract(&mut self) -> Result<EthAddress, Error> {
         if self.tx.data.is_none() {
             return Err(Error::MalformedData(
                 "Contract creation transaction is missing data!".to_string(),
             ));
         }
         let code = self.tx.data.clone().unwrap();
-        let mut stream = RlpStream::new_list(2);
-        stream
-            .append(&self.tx.from.as_ref().to_vec())
-            .append(&self.tx.nonce);
-        let rlp_data = stream.out();
-        let contract_address = EthAddress(Bytes::from(&keccak256(&rlp_data)[12..]));
-        let contract_data = EthContractData {
-            code,
-            storage: HashMap::default(),
-        };
+        let contract_address = build_contract_address(
+            &self.tx.from,
+            self.tx.nonce,
+            &CreateContractAddress::FromSenderAndNonce,
+            &code,
+        );
+        self.state.contracts.insert(
+            contract_address.clone(),
+            ContractState::created(self.tx.value_in_capacity()?),
+        );
 
         // Run contract on CKB to initialize real code
-        let (_gas_left, return_data, contract_data) =
-            self.call_evm(&contract_address, contract_data)?;
+        let (gas_left, return_data) = self.call_evm(&contract_address, code)?;
         if return_data.is_none() {
             return Err(Error::MalformedData(
                 "Initializer is missing return data".to_string(),
             ));
         }
-        // TODO: finalize code gas
-        let initialized_code = Bytes::from(&*return_data.unwrap());
-        let initialized_storage = contract_data.storage;
+        let return_data = return_data.unwrap();
+        let schedule = evm_schedule();
+        let deposit_gas =
+            ParityU256::from(return_data.len()) * ParityU256::from(schedule.create_data_gas);
+        if return_data.len() > schedule.create_data_limit || deposit_gas > gas_left {
+            return Err(Error::EVM("Out of gas for code deposit!".to_string()));
+        }
+        self.finalize_gas(&(gas_left - deposit_gas));
+        self.state
+            .contracts
+            .get_mut(&contract_address)
+            .expect("created contract must exist")
+            .data
+            .code = Bytes::from(&*return_data);
+        Ok(contract_address)
+    }
+
+    fn deposit(&mut self, address: &EthAddress, capacity: &Capacity) -> Result<(), Error> {
+        let contract = self
+            .state
+            .contracts
+            .get_mut(address)
+            .ok_or(Error::MalformedData("Contract is not loaded!".to_string()))?;
+        contract.capacity = Capacity(
+            contract
+                .capacity
+                .0
+                .safe_add(capacity.0)
+                .map_err(|_| Error::MalformedData("Capacity addition overflow".to_string()))?,
+        );
+        Ok(())
+    }
+
+    // Moves value from a contract to any account, the receiver is loaded as
+    // contract when possible, otherwise it's treated as a normal account.
+    fn transfer(&mut self, from: &EthAddress, to: &EthAddress, value: &U256) -> Result<(), Error> {
+        let capacity = wei_to_capacity(value)?;
+        if capacity.0 == 0u64.as_capacity() || from == to {
+            return Ok(());
+        }
+        if self.load_contract(to)?.is_some() {
+            // Destroyed contract cells are not recreated, value sent to them
+            // could not be burned since the sender's lock only allows fees
+            // paid for gas.
+            if self.state.destroyed.contains(to) {
+                return Err(Error::MalformedData(
+                    "Could not transfer to destroyed contract!".to_string(),
+                ));
+            }
+            self.withdraw(from, &capacity)?;
+            return self.deposit(to, &capacity);
+        }
+        let transferred = self
+            .state
+            .transfers
+            .get(to)
+            .map(|transferred| transferred.0)
+            .unwrap_or_else(|| 0u64.as_capacity())
+            .safe_add(capacity.0)
+            .map_err(|_| Error::MalformedData("Capacity addition overflow".to_string()))?;
+        if to != &self.tx.from && transferred < self.min_fund_cell_capacity(to)? {
+            return Err(Error::MalformedData(
+                "Transferred capacity is less than a fund cell occupies!".to_string(),
+            ));
+        }
+        self.withdraw(from, &capacity)?;
+        self.state
+            .transfers
+            .insert(to.clone(), Capacity(transferred));
+        Ok(())
+    }
 
-        let mut data = BytesMut::from(&[CellType::ContractMainCell as u8][..]);
-        data.extend_from_slice(&serialize(&EthContractData {
-            code: initialized_code,
-            storage: initialized_storage,
-        })?);
-        let data = JsonBytes::from_bytes(data.freeze());
-        let mut lock = Script::default();
-        lock.code_hash = CODE_HASH_CONTRACT_LOCK.into();
-        lock.args
-            .push(JsonBytes::from_bytes(contract_address.0.clone()));
-        self.build_ckb_transaction(data, lock, Capacity(0u64.as_capacity()))
+    // Capacity occupied by an empty fund cell of a normal account
+    fn min_fund_cell_capacity(&self, address: &EthAddress) -> Result<CoreCapacity, Error> {
+        let lock = build_lock_script(CODE_HASH_LOCK, address, self.loader.chain_id);
+        CoreCellOutput {
+            capacity: 0u64.as_capacity(),
+            data: Bytes::default(),
+            lock: lock.into(),
+            type_: None,
+        }
+        .occupied_capacity()
+        .map_err(|_| Error::MalformedData("Capacity error".to_string()))
+    }
+
+    fn withdraw(&mut self, address: &EthAddress, capacity: &Capacity) -> Result<(), Error> {
+        let contract = self
+            .state
+            .contracts
+            .get_mut(address)
+            .ok_or(Error::MalformedData("Contract is not loaded!".to_string()))?;
+        contract.capacity =
+            Capacity(contract.capacity.0.safe_sub(capacity.0).map_err(|_| {
+                Error::MalformedData("Contract capacity is not enough!".to_string())
+            })?);
+        Ok(())
+    }
+
+    // Contract cells that need to be consumed and recreated in the CKB
+    // transaction, target contract always comes first, followed by contracts
+    // that are created or modified by message calls. Destroyed contracts are
+    // consumed without being recreated, no capacity is left in them since
+    // refunding non-zero capacity to themselves or sending value to them
+    // afterwards is rejected.
+    fn build_contract_cells(
+        &self,
+        target_address: &EthAddress,
+    ) -> Result<(Vec<CellOutPoint>, Vec<CoreCellOutput>), Error> {
+        let mut addresses: Vec<&EthAddress> = self
+            .state
+            .contracts
+            .keys()
+            .filter(|address| *address != target_address)
+            .collect();
+        // Contracts are kept in a HashMap, sort them so the same call always
+        // builds the same transaction.
+        addresses.sort_by(|a, b| a.0.cmp(&b.0));
+        addresses.insert(0, target_address);
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        for address in addresses {
+            let contract = &self.state.contracts[address];
+            let destroyed = self.state.destroyed.contains(address);
+            if let Some(EthCell(main_cell_output, main_cell_out_point)) = &contract.main_cell {
+                if address != target_address
+                    && !destroyed
+                    && main_cell_output.capacity.0 == contract.capacity.0
+                    && self.initial_contracts.get(address) == Some(&contract.data)
+                {
+                    continue;
+                }
+                inputs.push(main_cell_out_point.clone());
+            }
+            if destroyed {
+                continue;
+            }
+            outputs.push(build_contract_cell(
+                address,
+                &contract.data,
+                &contract.capacity,
+                self.loader.chain_id,
+            )?);
+        }
+        Ok((inputs, outputs))
+    }
+
+    // New fund cells for normal accounts receiving value from contracts.
+    // Outputs other than the first one could not use the sender's lock, so
+    // value sent to the sender goes to the change cell instead.
+    fn build_transfer_cells(&self) -> Vec<CoreCellOutput> {
+        let mut transfers: Vec<(&EthAddress, &Capacity)> = self
+            .state
+            .transfers
+            .iter()
+            .filter(|(address, _)| *address != &self.tx.from)
+            .collect();
+        transfers.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        transfers
+            .into_iter()
+            .map(|(address, capacity)| {
+                let lock = build_lock_script(CODE_HASH_LOCK, address, self.loader.chain_id);
+                CoreCellOutput {
+                    capacity: capacity.0,
+                    data: Bytes::default(),
+                    lock: lock.into(),
+                    type_: None,
+                }
+            })
+            .collect()
     }
 
     fn call_evm(
         &mut self,
         contract_address: &EthAddress,
-        contract_data: EthContractData,
-    ) -> Result<(ParityU256, Option<ReturnData>, EthContractData), Error> {
+        code: Bytes,
+    ) -> Result<(ParityU256, Option<ReturnData>), Error> {
         let params = ActionParams {
             code_address: contract_address.into(),
-            code_hash: Some(keccak256(&contract_data.code).into()),
+            code_hash: Some(keccak256(&code).into()),
             address: contract_address.into(),
             sender: (&self.tx.from).into(),
             origin: (&self.tx.from).into(),
-            gas: to_parity_u256(&self.tx.fees()?),
-            gas_price: to_parity_u256(&self.tx.gas_price),
+            gas: to_parity_u256(&self.tx.gas_limit)
+                .saturating_sub(to_parity_u256(&intrinsic_gas(self.tx))),
+            gas_price: to_parity_u256(&self.tx.effective_gas_price()),
             value: ActionValue::Transfer(to_parity_u256(&self.tx.value)),
-            code: Some(Arc::new(contract_data.code.to_vec())),
+            code: Some(Arc::new(code.to_vec())),
             code_version: ParityU256::zero(),
             data: self.tx.data.clone().map(|bytes| bytes.to_vec()),
             call_type: CallType::Call,
             params_type: ParamsType::Separate,
         };
-        let schedule = Schedule::new_constantinople();
-        let exec = Factory::default().create(params, &schedule, 0);
-        let mut contract_runner = ContractRunner::new(self, contract_data);
-        let result = exec
-            .exec(&mut contract_runner)
-            .map_err(|_| Error::EVM("Trap is not yet supported".to_string()))??;
+        let result = self.exec(params, 0, false)?;
         let (gas_left, return_data) = match result {
             GasLeft::NeedsReturn {
                 data,
@@ -197,54 +662,77 @@ impl<'a> Runner<'a> {
                 if apply_state {
                     (gas_left, Some(data))
                 } else {
-                    return Err(Error::EVM("Reverted!".to_string()));
+                    return Err(Error::Reverted(Bytes::from(&*data)));
                 }
             }
             GasLeft::Known(gas_left) => (gas_left, None),
         };
-        Ok((gas_left, return_data, contract_runner.data))
+        Ok((gas_left, return_data))
+    }
+
+    fn exec(
+        &mut self,
+        params: ActionParams,
+        depth: usize,
+        is_static: bool,
+    ) -> ParityVmResult<GasLeft> {
+        let schedule = evm_schedule();
+        let address = EthAddress::from(&params.address);
+        let value = params.value.value();
+        let exec = Factory::default().create(params, &schedule, depth);
+        let mut contract_runner = ContractRunner::new(self, address, value, depth, is_static);
+        exec.exec(&mut contract_runner)
+            .map_err(|_| ParityVmError::Internal("Trap is not yet supported".to_string()))?
     }
 
+    // Sender cells always come first in the CKB transaction, followed by
+    // extra input cells. The first output is always the sender's main cell.
     fn build_ckb_transaction(
         &self,
-        data: JsonBytes,
-        lock: Script,
-        spare_capacity: Capacity,
+        extra_inputs: Vec<CellOutPoint>,
+        extra_outputs: Vec<CoreCellOutput>,
     ) -> Result<Transaction, Error> {
         let account = self
-            .loader
-            .load_account(&self.tx.from, self.block_number, false)?
+            .load_account(&self.tx.from)?
             .ok_or(Error::MalformedData(
                 "Account does not exist yet!".to_string(),
             ))?;
-        let value_capacity = self.tx.value_in_capacity()?;
-        let target_cell = CoreCellOutput {
-            capacity: value_capacity
-                .0
-                .safe_add(spare_capacity.0)
-                .map_err(|_| Error::MalformedData("Capacity addition overflow".to_string()))?,
-            data: data.into_bytes(),
-            lock: lock.into(),
-            type_: None,
-        };
-        if target_cell
-            .is_lack_of_capacity()
-            .map_err(|_| Error::MalformedData("Capacity error".to_string()))?
-        {
-            return Err(Error::MalformedData(
-                format!(
-                    "Capacity is not enough!, required: {:?} actual: {:?}",
-                    target_cell.occupied_capacity(),
-                    target_cell.capacity,
-                )
-                .to_string(),
-            ));
+        for output in &extra_outputs {
+            if output
+                .is_lack_of_capacity()
+                .map_err(|_| Error::MalformedData("Capacity error".to_string()))?
+            {
+                return Err(Error::MalformedData(
+                    format!(
+                        "Capacity is not enough!, required: {:?} actual: {:?}",
+                        output.occupied_capacity(),
+                        output.capacity,
+                    )
+                    .to_string(),
+                ));
+            }
         }
+        let value_capacity = self.tx.value_in_capacity()?;
+        let total_capacity = account.total_capacities()?;
+        // Sender must be able to afford the whole gas limit, while only
+        // gas actually used is charged.
+        total_capacity
+            .0
+            .safe_sub(self.tx.fees_in_capacity()?.0)
+            .and_then(|c| c.safe_sub(value_capacity.0))
+            .map_err(|_| Error::MalformedData("Account capacity is not enough!".to_string()))?;
+        let fees_capacity = wei_to_capacity(&self.tx.fees_with_gas(&self.gas_used)?)?;
+        let received_capacity = self
+            .state
+            .transfers
+            .get(&self.tx.from)
+            .map(|capacity| capacity.0)
+            .unwrap_or_else(|| 0u64.as_capacity());
         let change_capacity = Capacity(
-            account
-                .total_capacities()?
+            total_capacity
                 .0
-                .safe_sub(self.tx.fees_in_capacity()?.0)
+                .safe_add(received_capacity)
+                .and_then(|c| c.safe_sub(fees_capacity.0))
                 .and_then(|c| c.safe_sub(value_capacity.0))
                 .map_err(|_| Error::MalformedData("Account capacity is not enough!".to_string()))?,
         );
@@ -255,6 +743,20 @@ impl<'a> Runner<'a> {
         let mut change_data = BytesMut::from(&[CellType::NormalMainCell as u8][..]);
         change_data.extend_from_slice(&self.tx.nonce.to_le_bytes());
         let change_data = JsonBytes::from_bytes(change_data.freeze());
+        let mut outputs = vec![CellOutput {
+            capacity: change_capacity,
+            data: change_data,
+            lock: original_lock,
+            type_: None,
+        }];
+        outputs.extend(extra_outputs.into_iter().map(|output| output.into()));
+        let inputs: Vec<CellOutPoint> = account
+            .main_cell
+            .iter()
+            .chain(account.fund_cells.iter())
+            .map(|c| c.1.clone())
+            .chain(extra_inputs.into_iter())
+            .collect();
         let mut ckb_transaction = Transaction {
             version: Version(0),
             deps: vec![
@@ -267,76 +769,227 @@ impl<'a> Runner<'a> {
                     block_hash: None,
                 },
             ],
-            inputs: account
-                .fund_cells
+            inputs: inputs
                 .iter()
-                .map(|c| CellInput {
+                .map(|out_point| CellInput {
                     previous_output: OutPoint {
-                        cell: Some(c.1.clone()),
+                        cell: Some(out_point.clone()),
                         block_hash: None,
                     },
                     since: Unsigned(0),
                 })
                 .collect(),
-            outputs: vec![
-                CellOutput {
-                    capacity: change_capacity,
-                    data: change_data,
-                    lock: original_lock,
-                    type_: None,
-                },
-                target_cell.into(),
-            ],
-            witnesses: account
-                .fund_cells
-                .iter()
-                .map(|_| (&vec![]).into())
-                .collect(),
+            outputs,
+            witnesses: inputs.iter().map(|_| (&vec![]).into()).collect(),
         };
-        if let Some(main_cell) = account.main_cell {
-            ckb_transaction.inputs.insert(
-                0,
-                CellInput {
-                    previous_output: OutPoint {
-                        cell: Some(main_cell.1.clone()),
-                        block_hash: None,
-                    },
-                    since: Unsigned(0),
-                },
-            );
-            ckb_transaction.witnesses.insert(0, (&vec![]).into());
-        }
         ckb_transaction.witnesses[0] = (&vec![self.tx.raw.clone()]).into();
         Ok(ckb_transaction)
     }
 }
 
+fn build_contract_cell(
+    contract_address: &EthAddress,
+    contract_data: &EthContractData,
+    capacity: &Capacity,
+    chain_id: u64,
+) -> Result<CoreCellOutput, Error> {
+    let mut data = BytesMut::from(&[CellType::ContractMainCell as u8][..]);
+    data.extend_from_slice(&serialize(contract_data)?);
+    let lock = build_lock_script(CODE_HASH_CONTRACT_LOCK, contract_address, chain_id);
+    Ok(CoreCellOutput {
+        capacity: capacity.0,
+        data: data.freeze(),
+        lock: lock.into(),
+        type_: None,
+    })
+}
+
+fn build_contract_address(
+    sender: &EthAddress,
+    nonce: u64,
+    scheme: &CreateContractAddress,
+    code: &[u8],
+) -> EthAddress {
+    let hash = match scheme {
+        CreateContractAddress::FromSenderAndNonce => {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&sender.as_ref().to_vec()).append(&nonce);
+            keccak256(&stream.out())
+        }
+        CreateContractAddress::FromSenderSaltAndCodeHash(salt) => {
+            // EIP-1014: keccak256(0xff ++ sender ++ salt ++ keccak256(code))
+            let mut buffer = [0u8; 85];
+            buffer[0] = 0xff;
+            buffer[1..21].copy_from_slice(sender.as_ref());
+            buffer[21..53].copy_from_slice(&salt.to_fixed_bytes());
+            buffer[53..].copy_from_slice(&keccak256(code));
+            keccak256(&buffer[..])
+        }
+        CreateContractAddress::FromSenderAndCodeHash => {
+            let mut buffer = [0u8; 52];
+            buffer[..20].copy_from_slice(sender.as_ref());
+            buffer[20..].copy_from_slice(&keccak256(code));
+            keccak256(&buffer[..])
+        }
+    };
+    EthAddress(Bytes::from(&hash[12..]))
+}
+
+// keccak256("Error(string)")[..4]
+const REVERT_ERROR_SELECTOR: &[u8] = &[0x08, 0xc3, 0x79, 0xa0];
+// keccak256("Panic(uint256)")[..4]
+const REVERT_PANIC_SELECTOR: &[u8] = &[0x4e, 0x48, 0x7b, 0x71];
+
+// Decodes revert data produced by Solidity: either `Error(string)` from
+// revert / require, or `Panic(uint256)` from failed assertions and checked
+// arithmetic. Returns None for custom or empty revert data.
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    if data.len() < 36 {
+        return None;
+    }
+    let (selector, payload) = data.split_at(4);
+    match selector {
+        REVERT_ERROR_SELECTOR => {
+            let offset = ParityU256::from_big_endian(&payload[..32]);
+            if offset > ParityU256::from(payload.len()) {
+                return None;
+            }
+            let offset = offset.low_u64() as usize;
+            if payload.len() < offset + 32 {
+                return None;
+            }
+            let length = ParityU256::from_big_endian(&payload[offset..offset + 32]);
+            if length > ParityU256::from(payload.len() - offset - 32) {
+                return None;
+            }
+            let start = offset + 32;
+            let end = start + length.low_u64() as usize;
+            Some(String::from_utf8_lossy(&payload[start..end]).to_string())
+        }
+        REVERT_PANIC_SELECTOR => Some(format!(
+            "panic code {:#x}",
+            ParityU256::from_big_endian(&payload[..32])
+        )),
+        _ => None,
+    }
+}
+
 struct ContractRunner<'a, 'b> {
     pub runner: &'a mut Runner<'b>,
-    pub data: EthContractData,
+    // Address whose storage current frame works on
+    pub address: EthAddress,
+    // Value of current frame, DELEGATECALL passes it down to callee
+    pub value: ParityU256,
 
+    depth: usize,
+    is_static: bool,
     schedule: Schedule,
 }
 
 impl<'a, 'b> ContractRunner<'a, 'b> {
-    fn new(runner: &'a mut Runner<'b>, data: EthContractData) -> Self {
+    fn new(
+        runner: &'a mut Runner<'b>,
+        address: EthAddress,
+        value: ParityU256,
+        depth: usize,
+        is_static: bool,
+    ) -> Self {
         Self {
             runner,
-            data,
-            schedule: Schedule::new_constantinople(),
+            address,
+            value,
+            depth,
+            is_static,
+            schedule: evm_schedule(),
+        }
+    }
+
+    // Deploys returned code of a newly created contract after charging code
+    // deposit gas. The creating contract pays for the occupied capacity of
+    // the new contract cell on top of the value it sends, the sender's lock
+    // only allows the sender to pay for value and gas.
+    fn finalize_create(
+        &mut self,
+        contract_address: &EthAddress,
+        gas_left: ParityU256,
+        code: Bytes,
+    ) -> ContractCreateResult {
+        let deposit_gas =
+            ParityU256::from(code.len()) * ParityU256::from(self.schedule.create_data_gas);
+        if code.len() > self.schedule.create_data_limit || deposit_gas > gas_left {
+            return ContractCreateResult::Failed;
+        }
+        let contract = self
+            .runner
+            .state
+            .contracts
+            .get_mut(contract_address)
+            .expect("created contract must exist");
+        contract.data.code = code;
+        // Contracts destroyed by their constructor get no cell to fund
+        if self.runner.state.destroyed.contains(contract_address) {
+            return ContractCreateResult::Created(contract_address.into(), gas_left - deposit_gas);
+        }
+        let required_capacity = match build_contract_cell(
+            contract_address,
+            &contract.data,
+            &contract.capacity,
+            self.runner.loader.chain_id,
+        )
+        .and_then(|output| {
+            output
+                .occupied_capacity()
+                .map_err(|_| Error::MalformedData("Capacity error".to_string()))
+        }) {
+            Ok(capacity) => Capacity(capacity),
+            Err(e) => {
+                debug!("Calculating occupied capacity error: {:?}", e);
+                return ContractCreateResult::Failed;
+            }
+        };
+        let creator_address = self.address.clone();
+        if let Err(e) = self
+            .runner
+            .withdraw(&creator_address, &required_capacity)
+            .and_then(|_| self.runner.deposit(contract_address, &required_capacity))
+        {
+            debug!("Funding contract cell error: {:?}", e);
+            return ContractCreateResult::Failed;
         }
+        ContractCreateResult::Created(contract_address.into(), gas_left - deposit_gas)
+    }
+
+    fn code(&self, address: &ParityAddress) -> ParityVmResult<Option<Bytes>> {
+        self.runner
+            .load_code(&address.into())
+            .map_err(|e| ParityVmError::Internal(e.to_string()))
+    }
+
+    fn storage(&self) -> ParityVmResult<&HashMap<U256, U256>> {
+        self.runner
+            .state
+            .contracts
+            .get(&self.address)
+            .map(|contract| &contract.data.storage)
+            .ok_or_else(|| ParityVmError::Internal("Contract is not loaded!".to_string()))
     }
 }
 
 impl<'a, 'b> Ext for ContractRunner<'a, 'b> {
-    fn initial_storage_at(&self, _key: &ParityH256) -> ParityVmResult<ParityH256> {
-        unimplemented!()
+    fn initial_storage_at(&self, key: &ParityH256) -> ParityVmResult<ParityH256> {
+        let value = self
+            .runner
+            .initial_contracts
+            .get(&self.address)
+            .and_then(|data| data.storage.get(&parity_h256_to_numext_u256(key)))
+            .cloned()
+            .unwrap_or(U256::zero());
+        Ok(numext_u256_to_parity_h256(&value))
     }
 
     fn storage_at(&self, key: &ParityH256) -> ParityVmResult<ParityH256> {
         let value = self
-            .data
-            .storage
+            .storage()?
             .get(&parity_h256_to_numext_u256(key))
             .cloned()
             .unwrap_or(U256::zero());
@@ -344,86 +997,325 @@ impl<'a, 'b> Ext for ContractRunner<'a, 'b> {
     }
 
     fn set_storage(&mut self, key: ParityH256, value: ParityH256) -> ParityVmResult<()> {
-        self.data.storage.insert(
+        if self.is_static {
+            return Err(ParityVmError::MutableCallInStaticContext);
+        }
+        let contract = self
+            .runner
+            .state
+            .contracts
+            .get_mut(&self.address)
+            .ok_or_else(|| ParityVmError::Internal("Contract is not loaded!".to_string()))?;
+        contract.data.storage.insert(
             parity_h256_to_numext_u256(&key),
             parity_h256_to_numext_u256(&value),
         );
         Ok(())
     }
 
-    fn exists(&self, _address: &ParityAddress) -> ParityVmResult<bool> {
-        unimplemented!()
+    fn exists(&self, address: &ParityAddress) -> ParityVmResult<bool> {
+        Ok(self.code(address)?.is_some())
     }
 
-    fn exists_and_not_null(&self, _address: &ParityAddress) -> ParityVmResult<bool> {
-        unimplemented!()
+    // Every CKB cell holds capacity, an existing account is never empty
+    fn exists_and_not_null(&self, address: &ParityAddress) -> ParityVmResult<bool> {
+        self.exists(address)
     }
 
     fn origin_balance(&self) -> ParityVmResult<ParityU256> {
-        unimplemented!()
+        self.balance(&(&self.runner.tx.from).into())
     }
 
-    fn balance(&self, _address: &ParityAddress) -> ParityVmResult<ParityU256> {
-        unimplemented!()
+    fn balance(&self, address: &ParityAddress) -> ParityVmResult<ParityU256> {
+        self.runner
+            .balance(&address.into())
+            .map(|balance| to_parity_u256(&balance))
+            .map_err(|e| ParityVmError::Internal(e.to_string()))
     }
 
-    fn blockhash(&mut self, _number: &ParityU256) -> ParityH256 {
-        unimplemented!()
+    fn blockhash(&mut self, number: &ParityU256) -> ParityH256 {
+        let current = self.runner.block_number;
+        if *number >= ParityU256::from(current)
+            || *number < ParityU256::from(current.saturating_sub(BLOCKHASH_WINDOW))
+        {
+            return ParityH256::zero();
+        }
+        match self.runner.loader.load_block_hash(number.low_u64()) {
+            Ok(Some(block_hash)) => ParityH256::from_slice(block_hash.as_bytes()),
+            Ok(None) => ParityH256::zero(),
+            Err(e) => {
+                debug!("Loading block hash {} error: {:?}", number, e);
+                ParityH256::zero()
+            }
+        }
     }
 
     fn create(
         &mut self,
-        _gas: &ParityU256,
-        _value: &ParityU256,
-        _code: &[u8],
-        _parent_version: &ParityU256,
-        _address: CreateContractAddress,
+        gas: &ParityU256,
+        value: &ParityU256,
+        code: &[u8],
+        parent_version: &ParityU256,
+        address_scheme: CreateContractAddress,
         _trap: bool,
     ) -> ::std::result::Result<ContractCreateResult, TrapKind> {
-        unimplemented!()
+        let nonce = match self.runner.state.contracts.get_mut(&self.address) {
+            Some(contract) => {
+                contract.data.nonce += 1;
+                contract.data.nonce - 1
+            }
+            None => return Ok(ContractCreateResult::Failed),
+        };
+        let contract_address = build_contract_address(&self.address, nonce, &address_scheme, code);
+        match self.runner.load_contract(&contract_address) {
+            Ok(None) => (),
+            Ok(Some(_)) => {
+                debug!(
+                    "Contract {:x} already exists",
+                    ParityAddress::from(&contract_address)
+                );
+                return Ok(ContractCreateResult::Failed);
+            }
+            Err(e) => {
+                debug!(
+                    "Loading contract {:x} error: {:?}",
+                    ParityAddress::from(&contract_address),
+                    e
+                );
+                return Ok(ContractCreateResult::Failed);
+            }
+        }
+        let snapshot = self.runner.state.clone();
+        self.runner.state.contracts.insert(
+            contract_address.clone(),
+            ContractState::created(Capacity(0u64.as_capacity())),
+        );
+        let creator_address = self.address.clone();
+        if let Err(e) = self.runner.transfer(
+            &creator_address,
+            &contract_address,
+            &from_parity_u256(value),
+        ) {
+            debug!(
+                "Transferring endowment to {:x} error: {:?}",
+                ParityAddress::from(&contract_address),
+                e
+            );
+            self.runner.state = snapshot;
+            return Ok(ContractCreateResult::Failed);
+        }
+        let params = ActionParams {
+            code_address: (&contract_address).into(),
+            code_hash: Some(keccak256(code).into()),
+            address: (&contract_address).into(),
+            sender: (&self.address).into(),
+            origin: (&self.runner.tx.from).into(),
+            gas: *gas,
+            gas_price: to_parity_u256(&self.runner.tx.effective_gas_price()),
+            value: ActionValue::Transfer(*value),
+            code: Some(Arc::new(code.to_vec())),
+            code_version: *parent_version,
+            data: None,
+            call_type: CallType::None,
+            params_type: ParamsType::Embedded,
+        };
+        let result = match self.runner.exec(params, self.depth + 1, false) {
+            Ok(GasLeft::Known(gas_left)) => {
+                self.finalize_create(&contract_address, gas_left, Bytes::default())
+            }
+            Ok(GasLeft::NeedsReturn {
+                gas_left,
+                data,
+                apply_state,
+            }) => {
+                if apply_state {
+                    self.finalize_create(&contract_address, gas_left, Bytes::from(&*data))
+                } else {
+                    ContractCreateResult::Reverted(gas_left, data)
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "Creating contract {:x} error: {:?}",
+                    ParityAddress::from(&contract_address),
+                    e
+                );
+                ContractCreateResult::Failed
+            }
+        };
+        match result {
+            ContractCreateResult::Created(_, _) => (),
+            _ => self.runner.state = snapshot,
+        }
+        Ok(result)
     }
 
     fn call(
         &mut self,
-        _gas: &ParityU256,
-        _sender_address: &ParityAddress,
-        _receive_address: &ParityAddress,
-        _value: Option<ParityU256>,
-        _data: &[u8],
-        _code_address: &ParityAddress,
-        _call_type: CallType,
+        gas: &ParityU256,
+        sender_address: &ParityAddress,
+        receive_address: &ParityAddress,
+        value: Option<ParityU256>,
+        data: &[u8],
+        code_address: &ParityAddress,
+        call_type: CallType,
         _trap: bool,
     ) -> ::std::result::Result<MessageCallResult, TrapKind> {
-        unimplemented!()
+        let snapshot = self.runner.state.clone();
+        // Value is only given for CALL and CALLCODE, the latter sends value
+        // to the caller itself.
+        if let Some(value) = value {
+            if let Err(e) = self.runner.transfer(
+                &sender_address.into(),
+                &receive_address.into(),
+                &from_parity_u256(&value),
+            ) {
+                debug!("Transferring value to {:x} error: {:?}", receive_address, e);
+                self.runner.state = snapshot;
+                return Ok(MessageCallResult::Failed);
+            }
+        }
+        if let Some(precompile) = Precompile::from_address(&code_address.into()) {
+            let cost = precompile.cost(data);
+            if cost > *gas {
+                debug!("Out of gas calling precompile {}", precompile.name);
+                self.runner.state = snapshot;
+                return Ok(MessageCallResult::Failed);
+            }
+            return match precompile.execute(data) {
+                Ok(output) => {
+                    let size = output.len();
+                    Ok(MessageCallResult::Success(
+                        *gas - cost,
+                        ReturnData::new(output, 0, size),
+                    ))
+                }
+                Err(e) => {
+                    debug!("Calling precompile {} error: {:?}", precompile.name, e);
+                    self.runner.state = snapshot;
+                    Ok(MessageCallResult::Failed)
+                }
+            };
+        }
+        let code = match self.runner.load_contract(&code_address.into()) {
+            Ok(Some(code)) => code,
+            // Calling an account without code only moves value, which is
+            // already done above.
+            Ok(None) => return Ok(MessageCallResult::Success(*gas, ReturnData::empty())),
+            Err(e) => {
+                debug!("Loading contract {:x} error: {:?}", code_address, e);
+                self.runner.state = snapshot;
+                return Ok(MessageCallResult::Failed);
+            }
+        };
+        let params = ActionParams {
+            code_address: *code_address,
+            code_hash: Some(keccak256(&code).into()),
+            address: *receive_address,
+            sender: *sender_address,
+            origin: (&self.runner.tx.from).into(),
+            gas: *gas,
+            gas_price: to_parity_u256(&self.runner.tx.effective_gas_price()),
+            value: match value {
+                Some(value) => ActionValue::Transfer(value),
+                None => ActionValue::Apparent(self.value),
+            },
+            code: Some(Arc::new(code.to_vec())),
+            code_version: ParityU256::zero(),
+            data: Some(data.to_vec()),
+            call_type,
+            params_type: ParamsType::Separate,
+        };
+        let is_static = self.is_static || call_type == CallType::StaticCall;
+        let result = match self.runner.exec(params, self.depth + 1, is_static) {
+            Ok(GasLeft::Known(gas_left)) => {
+                MessageCallResult::Success(gas_left, ReturnData::empty())
+            }
+            Ok(GasLeft::NeedsReturn {
+                gas_left,
+                data,
+                apply_state,
+            }) => {
+                if apply_state {
+                    MessageCallResult::Success(gas_left, data)
+                } else {
+                    self.runner.state = snapshot;
+                    MessageCallResult::Reverted(gas_left, data)
+                }
+            }
+            Err(e) => {
+                debug!("Calling contract {:x} error: {:?}", code_address, e);
+                self.runner.state = snapshot;
+                MessageCallResult::Failed
+            }
+        };
+        Ok(result)
     }
 
-    fn extcode(&self, _address: &ParityAddress) -> ParityVmResult<Option<Arc<Vec<u8>>>> {
-        unimplemented!()
+    fn extcode(&self, address: &ParityAddress) -> ParityVmResult<Option<Arc<Vec<u8>>>> {
+        Ok(self.code(address)?.map(|code| Arc::new(code.to_vec())))
     }
 
-    fn extcodehash(&self, _address: &ParityAddress) -> ParityVmResult<Option<ParityH256>> {
-        unimplemented!()
+    // EIP-1052: hash of empty code for normal accounts, and zero for
+    // accounts that do not exist.
+    fn extcodehash(&self, address: &ParityAddress) -> ParityVmResult<Option<ParityH256>> {
+        Ok(self.code(address)?.map(|code| keccak256(&code).into()))
     }
 
-    fn extcodesize(&self, _address: &ParityAddress) -> ParityVmResult<Option<usize>> {
-        unimplemented!()
+    fn extcodesize(&self, address: &ParityAddress) -> ParityVmResult<Option<usize>> {
+        Ok(self.code(address)?.map(|code| code.len()))
     }
 
-    fn log(&mut self, _topics: Vec<ParityH256>, _data: &[u8]) -> ParityVmResult<()> {
-        unimplemented!()
+    fn log(&mut self, topics: Vec<ParityH256>, data: &[u8]) -> ParityVmResult<()> {
+        if self.is_static {
+            return Err(ParityVmError::MutableCallInStaticContext);
+        }
+        self.runner.state.logs.push(EthLog {
+            address: self.address.clone(),
+            topics: topics.iter().map(parity_h256_to_numext_h256).collect(),
+            data: Bytes::from(data),
+        });
+        Ok(())
     }
 
+    // Return data and state changes are handled in exec, nothing is left
+    // to do here besides reporting gas left.
     fn ret(
         self,
-        _gas: &ParityU256,
+        gas: &ParityU256,
         _data: &ReturnData,
         _apply_state: bool,
     ) -> ParityVmResult<ParityU256> {
-        unimplemented!()
+        Ok(*gas)
     }
 
-    fn suicide(&mut self, _refund_address: &ParityAddress) -> ParityVmResult<()> {
-        unimplemented!()
+    // All capacity of the contract cell, including the part occupied by
+    // code and storage, goes to the refund address.
+    fn suicide(&mut self, refund_address: &ParityAddress) -> ParityVmResult<()> {
+        if self.is_static {
+            return Err(ParityVmError::MutableCallInStaticContext);
+        }
+        let address = self.address.clone();
+        let balance = self
+            .runner
+            .balance(&address)
+            .map_err(|e| ParityVmError::Internal(e.to_string()))?;
+        if !balance.is_zero() {
+            // Capacity refunded to the contract itself would be burned, which
+            // the sender's lock does not allow.
+            if EthAddress::from(refund_address) == address {
+                return Err(ParityVmError::Internal(
+                    "Could not refund to the destroyed contract itself!".to_string(),
+                ));
+            }
+            self.runner
+                .transfer(&address, &refund_address.into(), &balance)
+                .map_err(|e| ParityVmError::Internal(e.to_string()))?;
+        }
+        if self.runner.state.destroyed.insert(address) {
+            self.runner.state.refund += self.schedule.suicide_refund_gas as i128;
+        }
+        Ok(())
     }
 
     fn schedule(&self) -> &Schedule {
@@ -431,22 +1323,213 @@ impl<'a, 'b> Ext for ContractRunner<'a, 'b> {
     }
 
     fn env_info(&self) -> &EnvInfo {
-        unimplemented!()
+        &self.runner.env_info
     }
 
     fn depth(&self) -> usize {
-        unimplemented!()
+        self.depth
     }
 
-    fn add_sstore_refund(&mut self, _value: usize) {
-        unimplemented!()
+    fn add_sstore_refund(&mut self, value: usize) {
+        self.runner.state.refund += value as i128;
     }
 
-    fn sub_sstore_refund(&mut self, _value: usize) {
-        unimplemented!()
+    fn sub_sstore_refund(&mut self, value: usize) {
+        self.runner.state.refund -= value as i128;
     }
 
     fn is_static(&self) -> bool {
-        unimplemented!()
+        self.is_static
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{tests::unsigned_transaction, AccessListItem};
+    use faster_hex::hex_decode;
+
+    fn address(s: &str) -> EthAddress {
+        EthAddress::parse(s).expect("address")
+    }
+
+    fn bytes(s: &str) -> Vec<u8> {
+        let mut b = vec![0u8; s.len() / 2];
+        hex_decode(s.as_bytes(), &mut b).expect("hex");
+        b
+    }
+
+    // ABI encoding of Error(string)
+    fn revert_error(reason: &str) -> Vec<u8> {
+        let mut data = REVERT_ERROR_SELECTOR.to_vec();
+        let mut word = [0u8; 32];
+        word[31] = 0x20;
+        data.extend_from_slice(&word);
+        ParityU256::from(reason.len()).to_big_endian(&mut word);
+        data.extend_from_slice(&word);
+        data.extend_from_slice(reason.as_bytes());
+        data.resize(data.len() + (32 - reason.len() % 32) % 32, 0);
+        data
+    }
+
+    #[test]
+    fn decode_revert_error() {
+        assert_eq!(
+            decode_revert_reason(&revert_error("Not enough Ether provided.")),
+            Some("Not enough Ether provided.".to_string())
+        );
+        assert_eq!(
+            decode_revert_reason(&revert_error("")),
+            Some("".to_string())
+        );
+    }
+
+    #[test]
+    fn decode_revert_panic() {
+        let mut data = REVERT_PANIC_SELECTOR.to_vec();
+        let mut code = [0u8; 32];
+        code[31] = 0x11;
+        data.extend_from_slice(&code);
+        assert_eq!(
+            decode_revert_reason(&data),
+            Some("panic code 0x11".to_string())
+        );
+    }
+
+    #[test]
+    fn decode_revert_invalid() {
+        assert_eq!(decode_revert_reason(&[]), None);
+        assert_eq!(decode_revert_reason(REVERT_ERROR_SELECTOR), None);
+        // Custom errors are not decoded
+        let mut data = revert_error("reason");
+        data[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(decode_revert_reason(&data), None);
+        // Length exceeds data
+        let mut data = revert_error("reason");
+        data[4 + 63] = 0xff;
+        assert_eq!(decode_revert_reason(&data), None);
+        // Offset exceeds data
+        let mut data = revert_error("reason");
+        data[4 + 31] = 0xff;
+        assert_eq!(decode_revert_reason(&data), None);
+    }
+
+    #[test]
+    fn contract_address_from_sender_and_nonce() {
+        let sender = address("0x6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0");
+        let expected = [
+            "0xcd234a471b72ba2f1ccf0a70fcaba648a5eecd8d",
+            "0x343c43a37d37dff08ae8c4a11544c718abb4fcf8",
+            "0xf778b86fa74e846c4f0a1fbd1335fe81c00a0c91",
+        ];
+        for (nonce, expected) in expected.iter().enumerate() {
+            assert_eq!(
+                build_contract_address(
+                    &sender,
+                    nonce as u64,
+                    &CreateContractAddress::FromSenderAndNonce,
+                    &[]
+                ),
+                address(expected)
+            );
+        }
+    }
+
+    // Examples from EIP-1014
+    #[test]
+    fn contract_address_from_sender_salt_and_code_hash() {
+        let zero_salt = "0000000000000000000000000000000000000000000000000000000000000000";
+        let vectors = [
+            (
+                "0x0000000000000000000000000000000000000000",
+                zero_salt,
+                "00",
+                "0x4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38",
+            ),
+            (
+                "0xdeadbeef00000000000000000000000000000000",
+                zero_salt,
+                "00",
+                "0xb928f69bb1d91cd65274e3c79d8986362984fda3",
+            ),
+            (
+                "0xdeadbeef00000000000000000000000000000000",
+                "000000000000000000000000feed000000000000000000000000000000000000",
+                "00",
+                "0xd04116cdd17bebe565eb2422f2497e06cc1c9833",
+            ),
+            (
+                "0x0000000000000000000000000000000000000000",
+                zero_salt,
+                "deadbeef",
+                "0x70f2b2914a2a4b783faefb75f459a580616fcb5e",
+            ),
+            (
+                "0x00000000000000000000000000000000deadbeef",
+                "00000000000000000000000000000000000000000000000000000000cafebabe",
+                "deadbeef",
+                "0x60f3f640a8508fc6a86d45df051962668e1e8ac7",
+            ),
+            (
+                "0x0000000000000000000000000000000000000000",
+                zero_salt,
+                "",
+                "0xe33c0c7f7df4809055c3eba6c09cfe4baf1bd9e0",
+            ),
+        ];
+        for (sender, salt, code, expected) in vectors.iter() {
+            let scheme = CreateContractAddress::FromSenderSaltAndCodeHash(ParityH256::from_slice(
+                &bytes(salt),
+            ));
+            assert_eq!(
+                build_contract_address(&address(sender), 0, &scheme, &bytes(code)),
+                address(expected)
+            );
+        }
+    }
+
+    fn transaction(
+        to: Option<EthAddress>,
+        data: &[u8],
+        access_list: Vec<AccessListItem>,
+    ) -> EthTransaction {
+        EthTransaction {
+            to,
+            data: Some(Bytes::from(data)),
+            access_list,
+            ..unsigned_transaction()
+        }
+    }
+
+    #[test]
+    fn intrinsic_gas_of_transactions() {
+        let to = Some(address("0x3535353535353535353535353535353535353535"));
+        assert_eq!(
+            intrinsic_gas(&transaction(to.clone(), &[], vec![])),
+            U256::from(21_000u64)
+        );
+        // 4 gas per zero byte and 16 gas per non-zero byte
+        assert_eq!(
+            intrinsic_gas(&transaction(to.clone(), &[0, 0, 1, 2, 3], vec![])),
+            U256::from(21_000u64 + 2 * 4 + 3 * 16)
+        );
+        assert_eq!(
+            intrinsic_gas(&transaction(None, &[0, 1], vec![])),
+            U256::from(53_000u64 + 4 + 16)
+        );
+        let access_list = vec![
+            AccessListItem {
+                address: JsonBytes::from_vec(vec![0x35; 20]),
+                storage_keys: vec![H256::zero(), H256::zero()],
+            },
+            AccessListItem {
+                address: JsonBytes::from_vec(vec![0x36; 20]),
+                storage_keys: vec![],
+            },
+        ];
+        assert_eq!(
+            intrinsic_gas(&transaction(to, &[], access_list)),
+            U256::from(21_000u64 + 2 * 2400 + 2 * 1900)
+        );
     }
 }
@@ -0,0 +1,633 @@
+use super::{
+    extract_lock_address, u256_to_u64, Error, EthAccount, EthAddress, EthCell, EthTransaction,
+    Loader, PoolTransactionObject, Runner, TransactionObject, TxPoolContent,
+};
+use ckb_jsonrpc_types::{CellOutPoint, Transaction, TxStatus, Unsigned};
+use numext_fixed_hash::H256;
+use numext_fixed_uint::U256;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Maximum number of transactions waiting for missing nonces per sender
+const MAX_QUEUED_TRANSACTIONS_PER_SENDER: usize = 64;
+// Number of recently rejected transactions kept for inspection
+const MAX_REJECTED_TRANSACTIONS: usize = 256;
+
+// An Ethereum transaction that is sent to CKB but not yet indexed
+pub struct PendingTransaction {
+    pub tx: EthTransaction,
+    pub ckb_transaction: Transaction,
+    pub ckb_transaction_hash: H256,
+    // Latest status reported by CKB
+    pub ckb_status: String,
+}
+
+// An Ethereum transaction that fails to be executed or sent to CKB
+pub struct RejectedTransaction {
+    pub tx: EthTransaction,
+    pub reason: String,
+}
+
+#[derive(Default)]
+struct SenderQueue {
+    // Transactions sent to CKB, nonces are consecutive
+    pending: BTreeMap<u64, PendingTransaction>,
+    // Transactions waiting for transactions with lower nonces
+    queued: BTreeMap<u64, EthTransaction>,
+}
+
+impl SenderQueue {
+    // Nonce following pending transactions, if there are any
+    fn next_pending_nonce(&self) -> Option<u64> {
+        self.pending.keys().next_back().map(|nonce| nonce + 1)
+    }
+
+    fn add_pending(&mut self, pending: PendingTransaction) {
+        self.pending.insert(pending.tx.nonce, pending);
+    }
+
+    fn add_queued(&mut self, tx: EthTransaction) -> Result<(), Error> {
+        if self.queued.contains_key(&tx.nonce) {
+            return Err(Error::MalformedData(
+                "Transaction with the same nonce is already queued!".to_string(),
+            ));
+        }
+        if self.queued.len() >= MAX_QUEUED_TRANSACTIONS_PER_SENDER {
+            return Err(Error::MalformedData(
+                "Too many queued transactions!".to_string(),
+            ));
+        }
+        self.queued.insert(tx.nonce, tx);
+        Ok(())
+    }
+
+    // Removes transactions with nonces lower than the indexed ones, those
+    // are either committed or replaced.
+    fn prune(&mut self, indexed_next_nonce: u64) {
+        self.pending = self.pending.split_off(&indexed_next_nonce);
+        self.queued = self.queued.split_off(&indexed_next_nonce);
+    }
+
+    // Takes the queued transaction whose nonce is next in line, the indexed
+    // next nonce is used when no transaction is pending.
+    fn take_promotable(&mut self, indexed_next_nonce: u64) -> Option<EthTransaction> {
+        let next_nonce = self.next_pending_nonce().unwrap_or(indexed_next_nonce);
+        self.queued.remove(&next_nonce)
+    }
+
+    // All transactions following a dropped one are chained off it, they
+    // are moved back to queued to be re-based as well.
+    fn rebase(&mut self, dropped_nonce: u64) {
+        for (nonce, pending) in self.pending.split_off(&dropped_nonce) {
+            info!("re-basing transaction: {:x}", pending.tx.hash());
+            self.queued.insert(nonce, pending.tx);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.queued.is_empty()
+    }
+}
+
+// Cells created and consumed by pending transactions, they are applied on
+// top of indexed state so new transactions can be chained off pending ones.
+#[derive(Default)]
+pub struct PendingCells {
+    spent: HashSet<CellOutPoint>,
+    added: HashMap<EthAddress, Vec<EthCell>>,
+}
+
+impl PendingCells {
+    fn add_transaction(
+        &mut self,
+        ckb_transaction: &Transaction,
+        ckb_transaction_hash: &H256,
+        chain_id: u64,
+    ) {
+        for input in &ckb_transaction.inputs {
+            if let Some(out_point) = &input.previous_output.cell {
+                self.spent.insert(out_point.clone());
+            }
+        }
+        for (i, output) in ckb_transaction.outputs.iter().enumerate() {
+            if let Some(eth_address) = extract_lock_address(&output.lock, chain_id) {
+                let cell_out_point = CellOutPoint {
+                    tx_hash: ckb_transaction_hash.clone(),
+                    index: Unsigned(i as u64),
+                };
+                self.added
+                    .entry(eth_address)
+                    .or_insert_with(Vec::new)
+                    .push(EthCell(output.clone(), cell_out_point));
+            }
+        }
+    }
+
+    pub fn apply(
+        &self,
+        eth_address: &EthAddress,
+        account: EthAccount,
+    ) -> Result<EthAccount, Error> {
+        // A pending transaction might already be indexed, skip cells that
+        // appear in both places.
+        let mut out_points = HashSet::new();
+        let (main_cells, fund_cells): (Vec<EthCell>, Vec<EthCell>) = account
+            .main_cell
+            .into_iter()
+            .chain(account.fund_cells.into_iter())
+            .chain(
+                self.added
+                    .get(eth_address)
+                    .into_iter()
+                    .flat_map(|cells| cells.iter().cloned()),
+            )
+            .filter(|cell| !self.spent.contains(&cell.1) && out_points.insert(cell.1.clone()))
+            .partition(|cell| cell.0.data.len() > 0);
+        if main_cells.len() > 1 {
+            return Err(Error::MalformedData("Invalid account cells".to_string()));
+        }
+        Ok(EthAccount {
+            main_cell: main_cells.get(0).cloned(),
+            fund_cells,
+        })
+    }
+}
+
+pub struct TxPool {
+    loader: Arc<Loader>,
+    // Serializes changes that execute and send transactions to CKB, so
+    // senders only need to be locked while reading or updating the queues
+    // and not during CKB requests.
+    submission: Mutex<()>,
+    senders: Mutex<HashMap<EthAddress, SenderQueue>>,
+    rejected: Mutex<VecDeque<RejectedTransaction>>,
+    subscribers: Mutex<Vec<Sender<H256>>>,
+}
+
+impl TxPool {
+    pub fn new(loader: Arc<Loader>) -> Self {
+        TxPool {
+            loader,
+            submission: Mutex::new(()),
+            senders: Mutex::new(HashMap::default()),
+            rejected: Mutex::new(VecDeque::default()),
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    // Sends the transaction to CKB right away when all transactions from the
+    // same sender with lower nonces are pending, otherwise the transaction
+    // is queued till the missing ones arrive.
+    pub fn add(&self, tx: EthTransaction) -> Result<H256, Error> {
+        let _submission = self.lock_submission()?;
+        let from = tx.from.clone();
+        let hash = tx.hash();
+        let indexed_next_nonce = self.prune(&from)?;
+        let next_nonce = self.next_nonce(&from, indexed_next_nonce)?;
+        if tx.nonce < next_nonce {
+            return Err(Error::MalformedData(format!(
+                "Nonce too low, next nonce is {}!",
+                next_nonce
+            )));
+        }
+        if tx.nonce == next_nonce {
+            self.submit(tx)?;
+        } else {
+            self.lock()?
+                .entry(from.clone())
+                .or_default()
+                .add_queued(tx)?;
+            debug!("Queued transaction: {:x}", hash);
+        }
+        self.promote(&from, indexed_next_nonce)?;
+        Ok(hash)
+    }
+
+    // Next nonce of an account, taking pending transactions into account
+    pub fn pending_nonce(&self, eth_address: &EthAddress) -> Result<U256, Error> {
+        let indexed_next_nonce = self.prune(eth_address)?;
+        Ok(U256::from(
+            self.next_nonce(eth_address, indexed_next_nonce)?,
+        ))
+    }
+
+    // Loads an account at the indexed tip with pending transactions applied
+    pub fn load_pending_account(
+        &self,
+        eth_address: &EthAddress,
+    ) -> Result<Option<EthAccount>, Error> {
+        self.prune(eth_address)?;
+        let block_number = self.loader.tip_block_number()?;
+        match self.loader.load_account(eth_address, block_number, true)? {
+            Some(account) => Ok(Some(self.pending_cells()?.apply(eth_address, account)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Receives hashes of transactions once they are sent to CKB
+    pub fn subscribe(&self) -> Result<Receiver<H256>, Error> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .map_err(|_| Error::MalformedData("Transaction pool is poisoned!".to_string()))?
+            .push(sender);
+        Ok(receiver)
+    }
+
+    // Hashes of transactions that are sent to CKB but not yet indexed
+    pub fn pending_hashes(&self) -> Result<Vec<H256>, Error> {
+        Ok(self
+            .lock()?
+            .values()
+            .flat_map(|queue| queue.pending.values().map(|pending| pending.tx.hash()))
+            .collect())
+    }
+
+    // Snapshot of all transactions in the pool, including recently rejected
+    // ones.
+    pub fn content(&self) -> Result<TxPoolContent<PoolTransactionObject>, Error> {
+        let chain_id = self.loader.chain_id;
+        let mut content = TxPoolContent {
+            pending: BTreeMap::default(),
+            queued: BTreeMap::default(),
+            rejected: BTreeMap::default(),
+        };
+        for (eth_address, queue) in self.lock()?.iter() {
+            for (nonce, pending) in &queue.pending {
+                content
+                    .pending
+                    .entry(eth_address.to_hex())
+                    .or_default()
+                    .insert(
+                        *nonce,
+                        PoolTransactionObject {
+                            transaction: TransactionObject::pending(&pending.tx, chain_id),
+                            ckb_transaction_hash: Some(pending.ckb_transaction_hash.clone()),
+                            ckb_status: pending.ckb_status.clone(),
+                            reject_reason: None,
+                        },
+                    );
+            }
+            for (nonce, tx) in &queue.queued {
+                content
+                    .queued
+                    .entry(eth_address.to_hex())
+                    .or_default()
+                    .insert(
+                        *nonce,
+                        PoolTransactionObject {
+                            transaction: TransactionObject::pending(tx, chain_id),
+                            ckb_transaction_hash: None,
+                            ckb_status: "queued".to_string(),
+                            reject_reason: None,
+                        },
+                    );
+            }
+        }
+        for rejected in self.lock_rejected()?.iter() {
+            content
+                .rejected
+                .entry(rejected.tx.from.to_hex())
+                .or_default()
+                .insert(
+                    rejected.tx.nonce,
+                    PoolTransactionObject {
+                        transaction: TransactionObject::pending(&rejected.tx, chain_id),
+                        ckb_transaction_hash: None,
+                        ckb_status: "rejected".to_string(),
+                        reject_reason: Some(rejected.reason.clone()),
+                    },
+                );
+        }
+        Ok(content)
+    }
+
+    // Drops transactions that are already indexed, and re-executes pending
+    // transactions whose CKB transactions are rejected or forked away on top
+    // of current state. The caller is responsible for calling this
+    // periodically.
+    pub fn maintain(&self) -> Result<(), Error> {
+        let _submission = self.lock_submission()?;
+        let addresses: Vec<EthAddress> = self.lock()?.keys().cloned().collect();
+        let mut indexed_next_nonces = HashMap::with_capacity(addresses.len());
+        for eth_address in addresses {
+            let indexed_next_nonce = self.prune(&eth_address)?;
+            indexed_next_nonces.insert(eth_address, indexed_next_nonce);
+        }
+        // CKB transactions are queried without holding the lock, pending
+        // transactions can only be changed by submission meanwhile.
+        let pending_hashes: Vec<(EthAddress, Vec<(u64, H256)>)> = self
+            .lock()?
+            .iter()
+            .map(|(eth_address, queue)| {
+                let hashes = queue
+                    .pending
+                    .iter()
+                    .map(|(nonce, pending)| (*nonce, pending.ckb_transaction_hash.clone()))
+                    .collect();
+                (eth_address.clone(), hashes)
+            })
+            .collect();
+        let mut client = self.loader.ckb_client();
+        for (eth_address, hashes) in pending_hashes {
+            let mut statuses = vec![];
+            let mut dropped_nonce = None;
+            for (nonce, ckb_transaction_hash) in hashes {
+                match client.get_transaction(ckb_transaction_hash).call()?.0 {
+                    Some(transaction_with_status) => {
+                        statuses.push((nonce, tx_status_name(&transaction_with_status.tx_status)));
+                    }
+                    None => {
+                        dropped_nonce = Some(nonce);
+                        break;
+                    }
+                }
+            }
+            if let Some(queue) = self.lock()?.get_mut(&eth_address) {
+                for (nonce, status) in statuses {
+                    if let Some(pending) = queue.pending.get_mut(&nonce) {
+                        pending.ckb_status = status.to_string();
+                    }
+                }
+                if let Some(dropped_nonce) = dropped_nonce {
+                    queue.rebase(dropped_nonce);
+                }
+            }
+            if dropped_nonce.is_some() {
+                self.promote(&eth_address, indexed_next_nonces[&eth_address])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lock_submission(&self) -> Result<MutexGuard<()>, Error> {
+        self.submission
+            .lock()
+            .map_err(|_| Error::MalformedData("Transaction pool is poisoned!".to_string()))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<HashMap<EthAddress, SenderQueue>>, Error> {
+        self.senders
+            .lock()
+            .map_err(|_| Error::MalformedData("Transaction pool is poisoned!".to_string()))
+    }
+
+    fn lock_rejected(&self) -> Result<MutexGuard<VecDeque<RejectedTransaction>>, Error> {
+        self.rejected
+            .lock()
+            .map_err(|_| Error::MalformedData("Transaction pool is poisoned!".to_string()))
+    }
+
+    // Removes transactions of the sender with nonces lower than the indexed
+    // ones, and returns the indexed next nonce, which is loaded without
+    // holding the lock.
+    fn prune(&self, eth_address: &EthAddress) -> Result<u64, Error> {
+        let indexed_next_nonce =
+            self.indexed_next_nonce(eth_address, self.loader.tip_block_number()?)?;
+        let mut senders = self.lock()?;
+        if let Some(queue) = senders.get_mut(eth_address) {
+            queue.prune(indexed_next_nonce);
+            if queue.is_empty() {
+                senders.remove(eth_address);
+            }
+        }
+        Ok(indexed_next_nonce)
+    }
+
+    // Sends queued transactions whose nonces are next in line
+    fn promote(&self, eth_address: &EthAddress, indexed_next_nonce: u64) -> Result<(), Error> {
+        loop {
+            let tx = match self
+                .lock()?
+                .get_mut(eth_address)
+                .and_then(|queue| queue.take_promotable(indexed_next_nonce))
+            {
+                Some(tx) => tx,
+                None => return Ok(()),
+            };
+            let hash = tx.hash();
+            if let Err(e) = self.submit(tx) {
+                warn!("Dropping queued transaction {:x}: {:?}", hash, e);
+                return Ok(());
+            }
+        }
+    }
+
+    fn submit(&self, tx: EthTransaction) -> Result<(), Error> {
+        let (ckb_transaction, ckb_transaction_hash) = match self.send(&tx) {
+            Ok(sent) => sent,
+            Err(e) => {
+                let mut rejected = self.lock_rejected()?;
+                if rejected.len() >= MAX_REJECTED_TRANSACTIONS {
+                    rejected.pop_front();
+                }
+                rejected.push_back(RejectedTransaction {
+                    tx,
+                    reason: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            let hash = tx.hash();
+            subscribers.retain(|subscriber| subscriber.send(hash.clone()).is_ok());
+        }
+        self.lock()?
+            .entry(tx.from.clone())
+            .or_default()
+            .add_pending(PendingTransaction {
+                tx,
+                ckb_transaction,
+                ckb_transaction_hash,
+                ckb_status: tx_status_name(&TxStatus::pending()).to_string(),
+            });
+        Ok(())
+    }
+
+    // Builds CKB transaction on top of pending ones and sends it to CKB,
+    // only pending cells are read under the lock.
+    fn send(&self, tx: &EthTransaction) -> Result<(Transaction, H256), Error> {
+        let pending_cells = self.pending_cells()?;
+        let block_number = self.loader.tip_block_number()?;
+        let ckb_transaction = Runner::new(&self.loader, tx, block_number)
+            .with_pending_cells(&pending_cells)
+            .run()?;
+        let ckb_transaction_hash = self
+            .loader
+            .ckb_client()
+            .send_transaction(ckb_transaction.clone())
+            .call()
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        debug!("Sent CKB transaction: {:x}", ckb_transaction_hash);
+        Ok((ckb_transaction, ckb_transaction_hash))
+    }
+
+    fn next_nonce(&self, eth_address: &EthAddress, indexed_next_nonce: u64) -> Result<u64, Error> {
+        Ok(self
+            .lock()?
+            .get(eth_address)
+            .and_then(SenderQueue::next_pending_nonce)
+            .unwrap_or(indexed_next_nonce))
+    }
+
+    fn indexed_next_nonce(
+        &self,
+        eth_address: &EthAddress,
+        block_number: u64,
+    ) -> Result<u64, Error> {
+        match self.loader.load_account(eth_address, block_number, true)? {
+            Some(account) => u256_to_u64(&account.next_nonce()?),
+            None => Ok(0),
+        }
+    }
+
+    fn pending_cells(&self) -> Result<PendingCells, Error> {
+        let mut pending_cells = PendingCells::default();
+        for queue in self.lock()?.values() {
+            for pending in queue.pending.values() {
+                pending_cells.add_transaction(
+                    &pending.ckb_transaction,
+                    &pending.ckb_transaction_hash,
+                    self.loader.chain_id,
+                );
+            }
+        }
+        Ok(pending_cells)
+    }
+}
+
+// Status is not exposed in CKB RPC types, so it is compared against dummy
+// ones here. Statuses unknown to this CKB version are never reported as
+// committed.
+fn tx_status_name(tx_status: &TxStatus) -> &'static str {
+    if tx_status.status == TxStatus::pending().status {
+        "pending"
+    } else if tx_status.status == TxStatus::proposed().status {
+        "proposed"
+    } else if tx_status.status == TxStatus::committed(H256::zero()).status {
+        "committed"
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::unsigned_transaction;
+    use ckb_jsonrpc_types::Version;
+
+    fn transaction(nonce: u64) -> EthTransaction {
+        EthTransaction {
+            nonce,
+            ..unsigned_transaction()
+        }
+    }
+
+    fn pending(nonce: u64) -> PendingTransaction {
+        PendingTransaction {
+            tx: transaction(nonce),
+            ckb_transaction: Transaction {
+                version: Version(0),
+                deps: vec![],
+                inputs: vec![],
+                outputs: vec![],
+                witnesses: vec![],
+            },
+            ckb_transaction_hash: H256::zero(),
+            ckb_status: "pending".to_string(),
+        }
+    }
+
+    fn nonces<T>(map: &BTreeMap<u64, T>) -> Vec<u64> {
+        map.keys().cloned().collect()
+    }
+
+    #[test]
+    fn queue_transactions_with_gaps() {
+        let mut queue = SenderQueue::default();
+        assert_eq!(queue.next_pending_nonce(), None);
+        queue.add_queued(transaction(3)).expect("queue");
+        queue.add_queued(transaction(2)).expect("queue");
+        assert!(queue.add_queued(transaction(2)).is_err());
+        assert_eq!(nonces(&queue.queued), vec![2, 3]);
+        // Queued transactions don't advance the pending nonce
+        assert_eq!(queue.next_pending_nonce(), None);
+    }
+
+    #[test]
+    fn limit_queued_transactions() {
+        let mut queue = SenderQueue::default();
+        for nonce in 0..MAX_QUEUED_TRANSACTIONS_PER_SENDER as u64 {
+            queue.add_queued(transaction(nonce + 1)).expect("queue");
+        }
+        assert!(queue
+            .add_queued(transaction(MAX_QUEUED_TRANSACTIONS_PER_SENDER as u64 + 1))
+            .is_err());
+    }
+
+    #[test]
+    fn promote_queued_transactions_in_nonce_order() {
+        let mut queue = SenderQueue::default();
+        queue.add_queued(transaction(3)).expect("queue");
+        queue.add_queued(transaction(1)).expect("queue");
+        queue.add_pending(pending(0));
+        // Pending transactions take precedence over the indexed nonce
+        let tx = queue.take_promotable(5).expect("promote");
+        assert_eq!(tx.nonce, 1);
+        // Nothing is promoted till the taken transaction becomes pending
+        assert!(queue.take_promotable(5).is_none());
+        queue.add_pending(pending(tx.nonce));
+        // Nonce 2 is missing
+        assert!(queue.take_promotable(5).is_none());
+        assert_eq!(nonces(&queue.pending), vec![0, 1]);
+        assert_eq!(nonces(&queue.queued), vec![3]);
+    }
+
+    #[test]
+    fn promote_queued_transactions_from_indexed_nonce() {
+        let mut queue = SenderQueue::default();
+        queue.add_queued(transaction(4)).expect("queue");
+        assert!(queue.take_promotable(3).is_none());
+        assert_eq!(queue.take_promotable(4).expect("promote").nonce, 4);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn name_tx_statuses() {
+        assert_eq!(tx_status_name(&TxStatus::pending()), "pending");
+        assert_eq!(tx_status_name(&TxStatus::proposed()), "proposed");
+        assert_eq!(
+            tx_status_name(&TxStatus::committed(H256::zero())),
+            "committed"
+        );
+    }
+
+    #[test]
+    fn rebase_dropped_transactions() {
+        let mut queue = SenderQueue::default();
+        for nonce in 0..3 {
+            queue.add_pending(pending(nonce));
+        }
+        queue.add_queued(transaction(4)).expect("queue");
+        queue.rebase(1);
+        assert_eq!(nonces(&queue.pending), vec![0]);
+        assert_eq!(nonces(&queue.queued), vec![1, 2, 4]);
+        assert_eq!(queue.next_pending_nonce(), Some(1));
+    }
+
+    #[test]
+    fn prune_indexed_transactions() {
+        let mut queue = SenderQueue::default();
+        for nonce in 0..3 {
+            queue.add_pending(pending(nonce));
+        }
+        queue.add_queued(transaction(4)).expect("queue");
+        queue.prune(2);
+        assert_eq!(nonces(&queue.pending), vec![2]);
+        assert_eq!(nonces(&queue.queued), vec![4]);
+        queue.prune(5);
+        assert!(queue.is_empty());
+    }
+}